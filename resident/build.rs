@@ -1,3 +1,6 @@
 fn main() {
-    println!("cargo:rustc-link-arg=resources/resources.res");
+    // Icons are compiled into a Windows resource file
+    if std::env::var_os("CARGO_CFG_WINDOWS").is_some() {
+        println!("cargo:rustc-link-arg=resources/resources.res");
+    }
 }
\ No newline at end of file
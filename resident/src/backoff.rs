@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+// Delay before the first restart, doubled with every restart that didn't last
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(300);
+// A restart counts as successful once the stubs stayed up this long
+const STABLE_PERIOD: Duration = Duration::from_secs(60);
+// Restarts in a row after which the entry is reported as degraded
+const DEGRADED_AFTER: u32 = 5;
+
+// Exponential backoff for restarting the stubs of an entry
+#[derive(Clone, Debug, Default)]
+pub struct Backoff {
+    attempts: u32,
+    last_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    pub const fn new() -> Backoff {
+        Backoff { attempts: 0, last_attempt: None }
+    }
+
+    /// True if a restart may be attempted at `now`.
+    #[must_use]
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_attempt {
+            Some(last) => now.saturating_duration_since(last) >= self.delay(),
+            None => true,
+        }
+    }
+
+    /// Records a restart attempt, successful or not. The stubs have to prove they stay up.
+    pub fn attempted(&mut self, now: Instant) {
+        self.attempts = self.attempts.saturating_add(1);
+        self.last_attempt = Some(now);
+    }
+
+    /// Forgets earlier attempts if the last one was long enough ago and nothing failed since.
+    pub fn settle(&mut self, now: Instant) {
+        if self.last_attempt.is_some_and(|last| now.saturating_duration_since(last) >= STABLE_PERIOD) {
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Backoff::new();
+    }
+
+    #[must_use]
+    pub fn is_degraded(&self) -> bool {
+        self.attempts >= DEGRADED_AFTER
+    }
+
+    fn delay(&self) -> Duration {
+        let exponent = self.attempts.saturating_sub(1).min(16);
+        FIRST_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_until_stable() {
+        let start = Instant::now();
+        let mut backoff = Backoff::new();
+        assert!(backoff.is_due(start));
+
+        let mut now = start;
+        for expected in [1, 2, 4, 8, 16, 32, 64, 128, 256, 300, 300] {
+            backoff.attempted(now);
+            assert!(!backoff.is_due(now + Duration::from_secs(expected) - Duration::from_millis(1)));
+            now += Duration::from_secs(expected);
+            assert!(backoff.is_due(now));
+        }
+        assert!(backoff.is_degraded());
+
+        // Up for a while after the last restart
+        backoff.settle(now + STABLE_PERIOD);
+        assert!(!backoff.is_degraded());
+        assert!(backoff.is_due(now + STABLE_PERIOD));
+    }
+
+    #[test]
+    fn settle_ignores_recent_attempts() {
+        let now = Instant::now();
+        let mut backoff = Backoff::new();
+        for _ in 0..DEGRADED_AFTER {
+            backoff.attempted(now);
+        }
+        backoff.settle(now + Duration::from_secs(1));
+        assert!(backoff.is_degraded());
+    }
+}
@@ -0,0 +1,202 @@
+use serde::Deserialize;
+use std::{fs, io, path::Path};
+
+use crate::schedule::Schedule;
+use crate::validate::validate;
+
+// Built-in catalog, used when there is no catalog file in the config folder
+const BUILTIN_CATALOG: &str = include_str!("../catalog.toml");
+const CATALOG_VERSION: u32 = 1;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    pub version: u32,
+    #[serde(rename = "category", default)]
+    pub categories: Vec<Category>,
+    #[serde(rename = "profile", default)]
+    pub profiles: Vec<Profile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Category {
+    // Not shown in the menu, lets signature packs extend existing categories
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "entry", default)]
+    pub entries: Vec<CatalogEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogEntry {
+    pub id: String,
+    pub name: String,
+    pub processes: Vec<Process>,
+    // Enables and disables the entry by weekday and time of day
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub schedule: Option<Schedule>,
+}
+
+// Stub spawned for an entry, written as an executable name or as a table with window details
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(from = "ProcessSpec")]
+pub struct Process {
+    pub name: String,
+    // Class and title of the stub window, for FindWindow and EnumWindows checks
+    pub window_class: Option<String>,
+    pub window_title: Option<String>,
+    // Full command line the stub is started with, including argv[0]; %VARIABLES% are expanded
+    pub command_line: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProcessSpec {
+    Name(String),
+    Table(ProcessTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProcessTable {
+    name: String,
+    window_class: Option<String>,
+    window_title: Option<String>,
+    command_line: Option<String>,
+}
+
+impl From<ProcessSpec> for Process {
+    fn from(spec: ProcessSpec) -> Process {
+        match spec {
+            ProcessSpec::Name(name) => Process { name, window_class: None, window_title: None, command_line: None },
+            ProcessSpec::Table(t) => Process {
+                name: t.name,
+                window_class: t.window_class,
+                window_title: t.window_title,
+                command_line: t.command_line,
+            },
+        }
+    }
+}
+
+// Named set of entries that can be enabled at once
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub id: String,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub name: String,
+    pub entries: Vec<String>,
+    // Enables only a random part of the entries and keeps changing it
+    pub rotation: Option<ProfileRotation>,
+    // Enables and disables the entries of the profile while it is active, unless they have their own
+    pub schedule: Option<Schedule>,
+}
+
+// Rotation mode of a profile: `count` random entries of the profile run at a time,
+// one of them is swapped for another one every min_interval_s to max_interval_s seconds
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileRotation {
+    pub count: usize,
+    pub min_interval_s: u64,
+    pub max_interval_s: u64,
+}
+
+impl Catalog {
+    pub fn builtin() -> io::Result<Catalog> {
+        Catalog::parse(BUILTIN_CATALOG).map_err(|e| annotate(e, "built-in catalog"))
+    }
+
+    /// Reads the catalog from `path`. Falls back to the built-in catalog if the file doesn't exist.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn load(path: &Path) -> io::Result<Catalog> {
+        match fs::read_to_string(path) {
+            Ok(text) => Catalog::parse(&text).map_err(|e| annotate(e, &path.to_string_lossy())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Catalog::builtin(),
+            Err(e) => Err(annotate(e, &path.to_string_lossy())),
+        }
+    }
+
+    pub fn parse(text: &str) -> io::Result<Catalog> {
+        let catalog: Catalog = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if catalog.version != CATALOG_VERSION {
+            let msg = format!("Unsupported catalog version {0}, expected {1}.", catalog.version, CATALOG_VERSION);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        let issues = validate(&catalog);
+        if !issues.is_empty() {
+            let msg = issues.iter().map(|i| i.to_string()).collect::<Vec<String>>().join("\n");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        Ok(catalog)
+    }
+}
+
+fn annotate(error: io::Error, source: &str) -> io::Error {
+    io::Error::new(error.kind(), format!("{0}: {1}", source, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_catalog_is_valid() {
+        let catalog = Catalog::builtin();
+        assert!(catalog.is_ok(), "{0}", catalog.err().map(|e| e.to_string()).unwrap_or_default());
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let err = Catalog::parse("version = 2").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reports_all_issues() {
+        let err = Catalog::parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["PRN.exe", "a.exe", "A.EXE"]
+        "#).err().unwrap();
+        assert_eq!(err.to_string().lines().count(), 2);
+    }
+
+    #[test]
+    fn process_is_name_or_table() {
+        let catalog = Catalog::parse(r#"
+            version = 1
+            [[category]]
+            id = "D"
+            name = "Debugger"
+            [[category.entry]]
+            id = "OLLY"
+            name = "OllyDbg"
+            processes = ["a.exe", { name = "ollydbg.exe", window_class = "OLLYDBG", window_title = "OllyDbg" }]
+        "#).unwrap();
+        let processes = &catalog.categories[0].entries[0].processes;
+        let plain = Process { name: "a.exe".to_string(), window_class: None, window_title: None, command_line: None };
+        assert_eq!(processes[0], plain);
+        assert_eq!(processes[1].window_class.as_deref(), Some("OLLYDBG"));
+        assert_eq!(processes[1].window_title.as_deref(), Some("OllyDbg"));
+        assert!(Catalog::parse(r#"
+            version = 1
+            [[category]]
+            id = "D"
+            name = "Debugger"
+            [[category.entry]]
+            id = "OLLY"
+            name = "OllyDbg"
+            processes = [{ name = "ollydbg.exe", window = "OLLYDBG" }]
+        "#).is_err());
+    }
+}
@@ -1,19 +1,36 @@
-#[allow(unused_imports)]
-use windows::{Win32::System::Registry::{HKEY, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE}};
-
-use crate::MenuId::*;
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const CATALOG_FILE: &str = "catalog.toml";
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const STATE_FILE: &str = "state.toml";
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const SETTINGS_FILE: &str = "settings.toml";
+// Decoys terminated by other processes, one line each, in the home folder
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const INCIDENT_LOG_FILE: &str = "incidents.log";
+// Signature packs, in the machine-wide config folder
+#[cfg_attr(not(windows), allow(dead_code))]
+pub const PACKS_FOLDER: &str = "packs/";
 
+// Defaults of the settings, see settings.rs
 pub const KEEP_STUB_COPIES: bool = true;
-pub const DEFAULT_PROCESS: &[crate::MenuId] = &[
-    GUEST_VIRTUALBOX,
-    DEBUGGER_IDA,
-    ANTIVIRUS_FORTINET,
-    FIREWALL_ZONEALARM,
-    TOOLS_PEID,
-    TOOLS_PROCESS_MONITOR,
-    TOOLS_PROCESS_EXPLORER,
-    TOOLS_TCPVIEW,
-    TOOLS_WIRESHARK,
-    TOOLS_PE_TOOLS,
-    TOOLS_SPYXX,
+pub const PROC_FOLDER: &str = "proc/";
+// How long a stub may take to exit on request before it is killed, ms
+pub const STOP_TIMEOUT_MS: u64 = 3000;
+// Longest random pause between the starts of two decoys, ms
+pub const LAUNCH_JITTER_MS: u64 = 1500;
+// Decoys started at the same timer tick at most
+pub const MAX_CONCURRENT_LAUNCHES: usize = 2;
+// Keys of the catalog entries enabled on the first run
+pub const DEFAULT_PROCESS: &[&str] = &[
+    "GUEST_VIRTUALBOX",
+    "DEBUGGER_IDA",
+    "ANTIVIRUS_FORTINET",
+    "FIREWALL_ZONEALARM",
+    "TOOLS_PEID",
+    "TOOLS_PROCESS_MONITOR",
+    "TOOLS_PROCESS_EXPLORER",
+    "TOOLS_TCPVIEW",
+    "TOOLS_WIRESHARK",
+    "TOOLS_PE_TOOLS",
+    "TOOLS_SPYXX",
 ];
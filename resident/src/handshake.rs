@@ -0,0 +1,225 @@
+// Launch protocol between des-resident and des-stub. The resident writes a request
+// to the stdin pipe of the stub, the stub answers on its stdout pipe once its window exists.
+// des-stub includes this file as is, keep it free of resident dependencies.
+//
+//     DES-STUB 1
+//     token=<64 hex digits, unique for the resident session>
+//     window_class=OLLYDBG
+//     window_title=OllyDbg
+//     status_channel=\\.\pipe\{00112233-4455-6677-8899-AABBCCDDEEFF}
+//     stub_id=3
+//     <empty line>
+//
+// Answer: "STARTED <token>". Values escape backslashes and line breaks.
+// The resident keeps the stdin pipe open afterwards: "SHUTDOWN" asks the stub to exit,
+// end of file means the resident is gone.
+
+// Each side uses its half of the protocol
+#![allow(dead_code)]
+
+use std::io::{self, BufRead, Write};
+
+const MAGIC: &str = "DES-STUB";
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const TOKEN_LEN: usize = 64;
+const STARTED: &str = "STARTED";
+const SHUTDOWN: &str = "SHUTDOWN";
+
+// What the stub pretends to be
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Persona {
+    pub window_class: Option<String>,
+    pub window_title: Option<String>,
+}
+
+// Where the stub introduces itself and reports its status, see status.rs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusChannel {
+    pub name: String,
+    pub stub_id: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub token: String,
+    pub persona: Persona,
+    pub status: Option<StatusChannel>,
+}
+
+impl Request {
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut text = format!("{0} {1}\ntoken={2}\n", MAGIC, PROTOCOL_VERSION, self.token);
+        if let Some(class) = &self.persona.window_class {
+            text += &format!("window_class={0}\n", escape(class));
+        }
+        if let Some(title) = &self.persona.window_title {
+            text += &format!("window_title={0}\n", escape(title));
+        }
+        if let Some(status) = &self.status {
+            text += &format!("status_channel={0}\nstub_id={1}\n", escape(&status.name), status.stub_id);
+        }
+        text.push('\n');
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+
+    /// Reads the request, an error means the stub wasn't started by the resident.
+    pub fn read<R: BufRead>(input: &mut R) -> io::Result<Request> {
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        if line.trim_end() != format!("{0} {1}", MAGIC, PROTOCOL_VERSION) {
+            return Err(invalid("not a launch request"));
+        }
+        let mut token: Option<String> = None;
+        let mut persona = Persona::default();
+        let mut channel: Option<String> = None;
+        let mut stub_id: Option<u32> = None;
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Err(invalid("request is truncated"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid("malformed request line"))?;
+            match key {
+                "token" => token = Some(value.to_string()),
+                "window_class" => persona.window_class = Some(unescape(value)),
+                "window_title" => persona.window_title = Some(unescape(value)),
+                "status_channel" => channel = Some(unescape(value)),
+                "stub_id" => stub_id = Some(value.parse().map_err(|_| invalid("malformed stub id"))?),
+                // Newer resident, unknown persona details are fine to skip
+                _ => {}
+            }
+        }
+        let token = token.filter(|t| is_token(t)).ok_or_else(|| invalid("missing or malformed token"))?;
+        // Both parts or none, the stub refuses to run without a channel
+        let status = channel.zip(stub_id).map(|(name, stub_id)| StatusChannel { name, stub_id });
+        Ok(Request { token, persona, status })
+    }
+}
+
+pub fn write_started<W: Write>(out: &mut W, token: &str) -> io::Result<()> {
+    writeln!(out, "{0} {1}", STARTED, token)?;
+    out.flush()
+}
+
+/// Checks the answer of the stub against the token it was given.
+pub fn read_started<R: BufRead>(input: &mut R, token: &str) -> io::Result<()> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    match line.trim_end().split_once(' ') {
+        Some((STARTED, answer)) if answer == token => Ok(()),
+        Some((STARTED, _)) => Err(invalid("stub answered with a foreign token")),
+        _ => Err(invalid("stub didn't confirm the start")),
+    }
+}
+
+pub fn write_shutdown<W: Write>(out: &mut W) -> io::Result<()> {
+    writeln!(out, "{0}", SHUTDOWN)?;
+    out.flush()
+}
+
+/// Blocks until the resident asks the stub to exit or goes away.
+pub fn wait_shutdown<R: BufRead>(input: &mut R) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line.trim_end() == SHUTDOWN => return,
+            // Newer resident, unknown commands are fine to skip
+            Ok(_) => {}
+        }
+    }
+}
+
+#[must_use]
+pub fn is_token(text: &str) -> bool {
+    text.len() == TOKEN_LEN && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "00112233445566778899AABBCCDDEEFF00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn request_round_trip() {
+        let request = Request {
+            token: TOKEN.to_string(),
+            persona: Persona {
+                window_class: Some("OLLYDBG".to_string()),
+                window_title: Some("Odd \\ title\nwith = signs".to_string()),
+            },
+            status: Some(StatusChannel { name: "\\\\.\\pipe\\{00112233-4455-6677-8899-AABBCCDDEEFF}".to_string(), stub_id: 3 }),
+        };
+        let mut buffer: Vec<u8> = Vec::new();
+        request.write(&mut buffer).unwrap();
+        // Anything after the empty line belongs to the stub, not to the request
+        buffer.extend_from_slice(b"trailing");
+        assert_eq!(Request::read(&mut buffer.as_slice()).unwrap(), request);
+    }
+
+    #[test]
+    fn rejects_missing_or_bad_token() {
+        assert!(Request::read(&mut "".as_bytes()).is_err());
+        assert!(Request::read(&mut "DES-STUB 1\nwindow_class=A\n\n".as_bytes()).is_err());
+        assert!(Request::read(&mut "DES-STUB 1\ntoken=1234\n\n".as_bytes()).is_err());
+        assert!(Request::read(&mut format!("DES-STUB 2\ntoken={0}\n\n", TOKEN).as_bytes()).is_err());
+        assert!(Request::read(&mut format!("DES-STUB 1\ntoken={0}\n", TOKEN).as_bytes()).is_err());
+        let request = Request::read(&mut format!("DES-STUB 1\r\ntoken={0}\r\nfuture=1\r\n\r\n", TOKEN).as_bytes()).unwrap();
+        assert_eq!(request.persona, Persona::default());
+        assert_eq!(request.status, None);
+    }
+
+    #[test]
+    fn started_answer_must_echo_token() {
+        let mut buffer: Vec<u8> = Vec::new();
+        write_started(&mut buffer, TOKEN).unwrap();
+        assert!(read_started(&mut buffer.as_slice(), TOKEN).is_ok());
+        assert!(read_started(&mut buffer.as_slice(), &TOKEN.replace('0', "1")).is_err());
+        assert!(read_started(&mut "".as_bytes(), TOKEN).is_err());
+    }
+
+    #[test]
+    fn shutdown_skips_unknown_commands() {
+        let mut buffer: Vec<u8> = b"PING\n".to_vec();
+        write_shutdown(&mut buffer).unwrap();
+        buffer.extend_from_slice(b"after");
+        let mut input = buffer.as_slice();
+        wait_shutdown(&mut input);
+        assert_eq!(input, b"after");
+        // End of file returns as well
+        wait_shutdown(&mut "PING\n".as_bytes());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How a stub ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StubExit {
+    Code(u32),
+    // The stub was killed by a signal
+    #[cfg(unix)]
+    Signal(i32),
+}
+
+impl fmt::Display for StubExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // NTSTATUS of a crash, e.g. 0xC0000005 for an access violation
+            StubExit::Code(code) if *code >= 0xC000_0000 => write!(f, "exception 0x{0:08X}", code),
+            StubExit::Code(code) => write!(f, "exit code {0}", code),
+            #[cfg(unix)]
+            StubExit::Signal(signal) => match signal_name(*signal) {
+                Some(name) => write!(f, "signal {0} ({1})", signal, name),
+                None => write!(f, "signal {0}", signal),
+            },
+        }
+    }
+}
+
+// Stubs only run on Windows so far, this is for a stub started as a std::process::Child on Linux
+#[cfg(unix)]
+impl From<std::process::ExitStatus> for StubExit {
+    fn from(status: std::process::ExitStatus) -> StubExit {
+        use std::os::unix::process::ExitStatusExt;
+        match (status.signal(), status.code()) {
+            (Some(signal), _) => StubExit::Signal(signal),
+            (None, Some(code)) => StubExit::Code(code as u32),
+            // Stopped or continued, not an exit
+            (None, None) => StubExit::Code(u32::MAX),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    let name = match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        6 => "SIGABRT",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        15 => "SIGTERM",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// A stub that ended without the resident asking it to. Somebody killed a decoy,
+// which is a strong hint that something on the machine hunts analysis tools.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnexpectedExit {
+    pub entry: String,
+    pub process: String,
+    pub exit: StubExit,
+    pub started: SystemTime,
+    pub exited: SystemTime,
+}
+
+impl UnexpectedExit {
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.exited.duration_since(self.started).unwrap_or_default()
+    }
+
+    /// One line of key=value pairs, easy to grep and to feed into a SIEM.
+    #[must_use]
+    pub fn to_log_line(&self) -> String {
+        format!(
+            "event=decoy_terminated entry={0} process={1} status={2} started={3} exited={4} uptime_s={5}",
+            quote(&self.entry),
+            quote(&self.process),
+            quote(&self.exit.to_string()),
+            unix_time(self.started),
+            unix_time(self.exited),
+            self.uptime().as_secs(),
+        )
+    }
+
+    /// Short text for the tray notification.
+    #[must_use]
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn to_notification(&self) -> String {
+        format!("{0} ({1}) was terminated by another process: {2}.", self.entry, self.process, self.exit)
+    }
+}
+
+/// Appends the exits to the incident log at `path`, in the format of `to_log_line`.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn append_to_log(path: &Path, exits: &[UnexpectedExit]) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    for exit in exits {
+        writeln!(file, "{0}", exit.to_log_line())?;
+    }
+    Ok(())
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{0}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_line_names_the_decoy() {
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let exit = UnexpectedExit {
+            entry: "VirtualBox \"guest\"".to_string(),
+            process: "VBoxService.exe".to_string(),
+            exit: StubExit::Code(0xC000_0005),
+            started,
+            exited: started + Duration::from_secs(90),
+        };
+        assert_eq!(
+            exit.to_log_line(),
+            "event=decoy_terminated entry=\"VirtualBox \\\"guest\\\"\" process=\"VBoxService.exe\" \
+            status=\"exception 0xC0000005\" started=1700000000 exited=1700000090 uptime_s=90"
+        );
+        assert_eq!(StubExit::Code(1).to_string(), "exit code 1");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn records_the_killing_signal() {
+        let status = std::process::Command::new("sh").args(["-c", "kill -9 $$"]).status().unwrap();
+        let exit = StubExit::from(status);
+        assert_eq!(exit, StubExit::Signal(9));
+        assert_eq!(exit.to_string(), "signal 9 (SIGKILL)");
+        let status = std::process::Command::new("sh").args(["-c", "exit 3"]).status().unwrap();
+        assert_eq!(StubExit::from(status), StubExit::Code(3));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::menu_ids::MenuId;
+use crate::random::Rng;
+
+// Entries waiting to be started, spread out in time so their stubs don't share one creation time.
+// Each entry is due a random pause of up to `jitter` after the previous one; the resident's timer
+// starts at most `max_per_tick` due entries at once, the rest wait for the next tick.
+#[derive(Debug)]
+pub struct LaunchQueue {
+    pending: VecDeque<(MenuId, Instant)>,
+    jitter: Duration,
+    max_per_tick: usize,
+}
+
+impl LaunchQueue {
+    pub const fn new(jitter: Duration, max_per_tick: usize) -> LaunchQueue {
+        LaunchQueue { pending: VecDeque::new(), jitter, max_per_tick }
+    }
+
+    /// Queues `ids` after the entries already waiting. Ids that are queued already keep their place.
+    pub fn schedule(&mut self, ids: &[MenuId], now: Instant, rng: &mut Rng) {
+        let mut due = self.pending.back().map_or(now, |(_, last)| (*last).max(now));
+        for id in ids {
+            if self.contains(id) {
+                continue;
+            }
+            due += rng.duration_up_to(self.jitter);
+            self.pending.push_back((*id, due));
+        }
+    }
+
+    /// Removes and returns the entries due at `now`, at most `max_per_tick` of them.
+    pub fn take_due(&mut self, now: Instant) -> Vec<MenuId> {
+        let mut due: Vec<MenuId> = Vec::new();
+        // A cap of 0 would stall the queue forever
+        while due.len() < self.max_per_tick.max(1) {
+            match self.pending.front() {
+                Some((id, at)) if *at <= now => {
+                    due.push(*id);
+                    self.pending.pop_front();
+                }
+                _ => break,
+            }
+        }
+        due
+    }
+
+    /// Drops `id` from the queue, true if it was waiting.
+    pub fn remove(&mut self, id: &MenuId) -> bool {
+        let before = self.pending.len();
+        self.pending.retain(|(i, _)| i != id);
+        self.pending.len() != before
+    }
+
+    #[must_use]
+    pub fn contains(&self, id: &MenuId) -> bool {
+        self.pending.iter().any(|(i, _)| i == id)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Moves the waiting entries of `other` over, with their due times, translating their ids
+    /// with `map`. Entries `map` doesn't know are dropped.
+    pub fn take_over<F: Fn(&MenuId) -> Option<MenuId>>(&mut self, other: &mut LaunchQueue, map: F) {
+        for (id, due) in other.pending.drain(..) {
+            if let Some(id) = map(&id) {
+                self.pending.push_back((id, due));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launches_are_spread_and_capped() {
+        let jitter = Duration::from_millis(500);
+        let mut queue = LaunchQueue::new(jitter, 2);
+        let mut rng = Rng::new(7);
+        let now = Instant::now();
+        let ids: Vec<MenuId> = (100..110).map(MenuId).collect();
+        queue.schedule(&ids, now, &mut rng);
+        queue.schedule(&[MenuId(100)], now, &mut rng);
+        assert_eq!(queue.pending.iter().map(|(id, _)| *id).collect::<Vec<MenuId>>(), ids);
+
+        // Due times never go back and the whole set is spread over at most 10 pauses
+        let dues: Vec<Instant> = queue.pending.iter().map(|(_, at)| *at).collect();
+        assert!(dues.windows(2).all(|w| w[0] <= w[1]));
+        assert!(dues[9] <= now + jitter * 10);
+
+        assert!(queue.remove(&MenuId(101)));
+        assert!(!queue.contains(&MenuId(101)));
+        // Far in the future everything is due, but only two per tick
+        let later = now + jitter * 11;
+        assert_eq!(queue.take_due(later), vec![MenuId(100), MenuId(102)]);
+        assert_eq!(queue.take_due(later), vec![MenuId(103), MenuId(104)]);
+        assert!(queue.take_due(now - Duration::from_millis(1)).is_empty());
+    }
+
+    #[test]
+    fn take_over_keeps_known_entries() {
+        let mut rng = Rng::new(1);
+        let now = Instant::now();
+        let mut old = LaunchQueue::new(Duration::ZERO, 0);
+        old.schedule(&[MenuId(100), MenuId(101)], now, &mut rng);
+        let mut fresh = LaunchQueue::new(Duration::ZERO, 0);
+        fresh.take_over(&mut old, |id| if id.0 == 100 { Some(MenuId(200)) } else { None });
+        assert!(old.is_empty());
+        assert_eq!(fresh.take_due(now), vec![MenuId(200)]);
+    }
+}
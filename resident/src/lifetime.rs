@@ -0,0 +1,160 @@
+// Ties the lifetime of stubs to the resident. Stubs live in a job object that kills them when
+// the resident's last handle to it goes away, on a normal exit as well as on a crash. Stubs also
+// exit on their own once their stdin pipe breaks, but that doesn't cover a hung stub.
+// Stubs a previous session left behind anyway, e.g. ones that couldn't be put into the job,
+// are found at startup by their image: a file in the proc folder with the stub's hash.
+
+#[cfg(windows)]
+use std::collections::BTreeMap;
+#[cfg(windows)]
+use std::io;
+#[cfg(windows)]
+use std::sync::OnceLock;
+
+#[cfg(windows)]
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::Foundation::*,
+    Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS},
+    Win32::System::JobObjects::*,
+    Win32::System::Threading::*,
+};
+
+// How long a killed leftover stub may take to go away before its copy is removed, ms
+#[cfg(windows)]
+const LEFTOVER_EXIT_WAIT_MS: u32 = 1000;
+
+/// True if `image` is a file directly inside `folder`. Both are Windows paths, compared
+/// case insensitively, with either kind of separator and with or without the `\\?\` prefix.
+#[must_use]
+pub fn is_in_folder(image: &str, folder: &str) -> bool {
+    let image = normalize(image);
+    let mut folder = normalize(folder);
+    if !folder.ends_with('\\') {
+        folder.push('\\');
+    }
+    match image.strip_prefix(&folder) {
+        Some(name) => !name.is_empty() && !name.contains('\\'),
+        None => false,
+    }
+}
+
+fn normalize(path: &str) -> String {
+    let path = path.replace('/', "\\").to_lowercase();
+    match path.strip_prefix("\\\\?\\") {
+        Some(stripped) => stripped.to_string(),
+        None => path,
+    }
+}
+
+/// Puts the started but still suspended stub into the kill-on-close job of this session.
+#[cfg(windows)]
+pub fn assign_to_job(process: HANDLE) -> io::Result<()> {
+    let job = session_job()?;
+    unsafe { AssignProcessToJobObject(job, process) }.ok()?;
+    Ok(())
+}
+
+// Created with the first stub and never closed, the system closes it when the resident exits
+#[cfg(windows)]
+fn session_job() -> io::Result<HANDLE> {
+    static JOB: OnceLock<HANDLE> = OnceLock::new();
+    if let Some(job) = JOB.get() {
+        return Ok(*job);
+    }
+    let job = unsafe { CreateJobObjectW(None, PCWSTR::null()) }?;
+    let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+    limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    let res = unsafe {
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &limits as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        ).ok()
+    };
+    if let Err(e) = res {
+        unsafe { CloseHandle(job) };
+        return Err(e.into());
+    }
+    Ok(*JOB.get_or_init(|| job))
+}
+
+/// Kills the processes running from a stub image in `proc_folder`, i.e. stubs of a previous session,
+/// and returns their image paths. `is_stub` checks the image file, so a misconfigured folder
+/// doesn't cost the user other programs. Processes that can't be opened aren't ours and are skipped.
+#[cfg(windows)]
+pub fn kill_leftover_stubs<F: Fn(&str) -> bool>(proc_folder: &str, is_stub: F) -> io::Result<Vec<String>> {
+    let folder = match std::fs::canonicalize(proc_folder) {
+        Ok(folder) => folder.to_string_lossy().to_string(),
+        // Nothing was ever started from there
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }?;
+    let mut entry = PROCESSENTRY32W { dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+    let mut killed = Vec::new();
+    // Images seen so far, several processes may run from the same one
+    let mut checked: BTreeMap<String, bool> = BTreeMap::new();
+    let mut is_stub_image = |image: &str| -> bool {
+        if !is_in_folder(image, &folder) {
+            return false;
+        }
+        *checked.entry(image.to_string()).or_insert_with(|| is_stub(image))
+    };
+    let mut more = unsafe { Process32FirstW(snapshot, &mut entry) }.as_bool();
+    while more {
+        if entry.th32ProcessID != std::process::id() {
+            if let Some(image) = kill_if_stub(entry.th32ProcessID, &mut is_stub_image) {
+                killed.push(image);
+            }
+        }
+        more = unsafe { Process32NextW(snapshot, &mut entry) }.as_bool();
+    }
+    unsafe { CloseHandle(snapshot) };
+    Ok(killed)
+}
+
+#[cfg(windows)]
+fn kill_if_stub<F: FnMut(&str) -> bool>(pid: u32, is_stub_image: &mut F) -> Option<String> {
+    let access = PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_TERMINATE | PROCESS_SYNCHRONIZE;
+    let process = unsafe { OpenProcess(access, false, pid) }.ok()?;
+    let killed = match image_path(process) {
+        Some(image) if is_stub_image(&image) => unsafe {
+            if TerminateProcess(process, 1).as_bool() {
+                // Its copy can't be removed while it runs
+                WaitForSingleObject(process, LEFTOVER_EXIT_WAIT_MS);
+                Some(image)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    };
+    unsafe { CloseHandle(process) };
+    killed
+}
+
+#[cfg(windows)]
+fn image_path(process: HANDLE) -> Option<String> {
+    let mut buffer = [0u16; MAX_PATH as usize];
+    let mut size = buffer.len() as u32;
+    unsafe { QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut size) }
+        .as_bool()
+        .then(|| String::from_utf16_lossy(&buffer[..size as usize]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_files_directly_in_folder() {
+        let folder = "\\\\?\\C:\\Users\\me\\AppData\\Local\\Temp\\des\\proc";
+        assert!(is_in_folder("C:\\Users\\me\\AppData\\Local\\Temp\\des\\proc\\OllyDbg.exe", folder));
+        assert!(is_in_folder("c:/users/ME/appdata/local/temp/des/proc/ollydbg.exe", "C:\\Users\\me\\AppData\\Local\\Temp\\des\\proc\\"));
+        assert!(!is_in_folder("C:\\Users\\me\\AppData\\Local\\Temp\\des\\proc\\sub\\ollydbg.exe", folder));
+        assert!(!is_in_folder("C:\\Users\\me\\AppData\\Local\\Temp\\des\\process.exe", folder));
+        assert!(!is_in_folder("C:\\Users\\me\\AppData\\Local\\Temp\\des\\proc\\", folder));
+    }
+}
@@ -1,5 +1,6 @@
 #![windows_subsystem = "windows"]
 
+#[cfg(windows)]
 use windows::{
     w,
     core::{
@@ -10,12 +11,12 @@ use windows::{
     Win32::Graphics::Gdi::ValidateRect,
     Win32::System::LibraryLoader::GetModuleHandleW,
     Win32::UI::Shell::{
-        Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW, NOTIFY_ICON_MESSAGE, NIM_MODIFY,
+        Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_WARNING, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW,
+        NOTIFY_ICON_MESSAGE, NIM_MODIFY,
     },
     Win32::UI::WindowsAndMessaging::*,
 };
 
-use cfg_if;
 cfg_if::cfg_if! {
     if #[cfg(feature = "logger")] {
         #[macro_use]
@@ -30,48 +31,115 @@ cfg_if::cfg_if! {
 
 extern crate sha2;
 
-#[macro_use]
-extern crate num_derive;
-use num_traits::FromPrimitive;
+// Other systems build only the platform independent part, to run its unit tests
+cfg_if::cfg_if! {
+    if #[cfg(any(windows, test))] {
+        mod switch;
+        mod backoff;
+        mod catalog;
+        mod config;
+        mod handshake;
+        mod incident;
+        mod launch;
+        mod lifetime;
+        mod menu_ids;
+        mod monitor;
+        mod pack;
+        mod random;
+        mod rotation;
+        mod schedule;
+        mod settings;
+        mod status;
+        mod validate;
+        mod watcher;
+    }
+}
 
-mod autostart;
-use autostart::AutoStart;
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        mod autostart;
+        use autostart::AutoStart;
 
-mod menu_entry;
-mod release;
+        mod saved_state;
 
-mod switch;
-use switch::Switch;
+        mod menu_entry;
+        mod release;
+        mod stub_process;
 
-mod config;
-use config::DEFAULT_PROCESS;
+        use switch::Switch;
+        use catalog::Catalog;
+        use config::{CATALOG_FILE, INCIDENT_LOG_FILE, PACKS_FOLDER, SETTINGS_FILE, STATE_FILE};
+        use settings::Settings;
 
-mod convert;
-use convert::to_pcwstr;
+        mod convert;
+        use convert::to_pcwstr;
 
-mod menu_state;
-use menu_state::MenuState;
+        mod menu_state;
+        use menu_state::MenuState;
 
-mod menu_ids;
-use menu_ids::MenuId;
+        use menu_ids::MenuId;
+        use saved_state::SavedState;
+        use schedule::SystemClock;
 
-mod menu_tray;
-mod tray_menu_state;
-use tray_menu_state::TrayMenuState;
+        mod menu_tray;
+        mod tray_menu_state;
+        use tray_menu_state::TrayMenuState;
 
+        use watcher::FileWatcher;
+        use std::cell::RefCell;
+        use std::path::PathBuf;
+    }
+}
+
+// Can't go into cfg_if!, macro-expanded #[macro_export] macros can't be referred by path
+#[cfg(windows)]
 #[macro_use]
 mod macros;
 
-// ===== Constants =====
-const TRAY_ICON_ID: u32 = 5;
-const TRAY_MESSAGE: u32 = WM_APP + 1;
-const LRESULT_SUCCESS: LRESULT = LRESULT(0);
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        // ===== Constants =====
+        const TRAY_ICON_ID: u32 = 5;
+        const TRAY_MESSAGE: u32 = WM_APP + 1;
+        const LRESULT_SUCCESS: LRESULT = LRESULT(0);
+        const RELOAD_TIMER_ID: usize = 1;
+        // How often config files are checked for changes, ms
+        const RELOAD_INTERVAL: u32 = 2000;
+        const RECONCILE_TIMER_ID: usize = 2;
+        // How often stubs of enabled entries are checked, ms
+        const RECONCILE_INTERVAL: u32 = 1000;
+        const LAUNCH_TIMER_ID: usize = 3;
+        // How often queued entries are checked for their turn, ms
+        const LAUNCH_INTERVAL: u32 = 100;
+
+        // ===== State of the application =====
+        static mut TRAY_MENU_STATE: TrayMenuState = TrayMenuState::new();
+        static mut MENU_STATE: MenuState = MenuState::new();
+        static mut AUTOSTART: AutoStart = AutoStart::new();
+
+        // Where the configuration and the files of the resident live and what is watched of them,
+        // only the window thread uses it
+        struct ConfigFiles {
+            // Log files and stub copies, from the settings
+            home_folder: String,
+            // Per-user settings, catalog and saved state
+            config_folder: String,
+            // Machine-wide settings and signature packs
+            machine_folder: String,
+            watcher: FileWatcher,
+        }
 
-// ===== State of the application =====
-static mut TRAY_MENU_STATE: TrayMenuState = TrayMenuState::new();
-static mut MENU_STATE: MenuState = MenuState::new();
-static mut AUTOSTART: AutoStart = AutoStart::new();
-static mut HOME_FOLDER: String = String::new();
+        impl ConfigFiles {
+            const fn new() -> ConfigFiles {
+                ConfigFiles { home_folder: String::new(), config_folder: String::new(), machine_folder: String::new(), watcher: FileWatcher::new() }
+            }
+        }
+
+        thread_local! {
+            static CONFIG_FILES: RefCell<ConfigFiles> = const { RefCell::new(ConfigFiles::new()) };
+        }
+    }
+}
 
 #[cfg(windows)]
 fn main() -> Result<()> {
@@ -82,13 +150,49 @@ fn main() -> Result<()> {
     let active_icon_res = PCWSTR(18 as *const u16);
     let paused_icon_res = PCWSTR(19 as *const u16);
     unsafe {
-        HOME_FOLDER = std::env::var("TEMP").unwrap_or("C:/Temp".to_owned()) + "/des/";
+        CONFIG_FILES.with(|files| {
+            let mut files = files.borrow_mut();
+            files.config_folder = std::env::var("APPDATA").unwrap_or("C:/Temp".to_owned()) + "/des/";
+            files.machine_folder = std::env::var("PROGRAMDATA").unwrap_or("C:/ProgramData".to_owned()) + "/des/";
+        });
+        let mut settings = Settings::default();
+        let settings_res = load_settings(&mut settings);
+        if settings_res.is_err() {
+            // Don't apply half of the files
+            settings = Settings::default();
+        }
+        CONFIG_FILES.with(|files| files.borrow_mut().home_folder = settings.home_folder.clone());
 
         #[cfg(feature = "logger")]
-        let _ = WriteLogger::init(LevelFilter::Debug, Config::default(), File::create(HOME_FOLDER.clone() + "log.txt").unwrap());
-        #[cfg(feature = "logger")] debug!("App started. Home folder is {0}", HOME_FOLDER);
+        let _ = WriteLogger::init(LevelFilter::Debug, Config::default(), File::create(settings.home_folder.clone() + "log.txt").unwrap());
+        #[cfg(feature = "logger")] debug!("App started. Home folder is {0}", settings.home_folder);
 
-        MENU_STATE.init_menu_entries();
+        match settings_res {
+            Ok(warnings) => report_settings_warnings(HWND(0), &warnings),
+            Err(e) => {
+                let err: String = "Can't load settings, using defaults. ".to_string() + &e.to_string();
+                MessageBoxW(HWND(0), to_pcwstr(&err).1, w!("Error"), MB_OK | MB_ICONERROR);
+            }
+        }
+        #[cfg(feature = "logger")] debug!("Settings loaded.");
+
+        clean_up_leftover_stubs(&settings);
+
+        let mut catalog = Catalog::load(&catalog_path())
+            .or_else(|e| {
+                let err: String = "Can't load decoy catalog, using the built-in one. ".to_string() + &e.to_string();
+                MessageBoxW(HWND(0), to_pcwstr(&err).1, w!("Error"), MB_OK | MB_ICONERROR);
+                Catalog::builtin()
+            })
+            .unwrap_or_default();
+        let pack_problems = pack::merge_packs(&mut catalog, &packs_path(), &settings.trusted_keys);
+        report_pack_problems(HWND(0), &pack_problems);
+        #[cfg(feature = "logger")] debug!("Catalog loaded.");
+
+        if let Err(e) = MENU_STATE.init_menu_entries(&catalog, &settings) {
+            let err: String = "Can't initialize menu entries. ".to_string() + &e.to_string();
+            MessageBoxW(HWND(0), to_pcwstr(&err).1, w!("Error"), MB_OK | MB_ICONERROR);
+        }
         #[cfg(feature = "logger")] debug!("Menu entries initialized.");
 
         module_handle = GetModuleHandleW(None)?;
@@ -101,15 +205,32 @@ fn main() -> Result<()> {
         cursor = LoadCursorW(None, IDC_ARROW)?;
         assert!(!cursor.is_invalid());
 
-        for m in DEFAULT_PROCESS {
-            let res = MENU_STATE.enable(m);
-            if let Err(e) = res {
-                let err: String = "Can't autorun default processes. ".to_string() + &e.to_string();
+        let saved_state = SavedState::load(&state_path())
+            .unwrap_or_else(|e| {
+                let err: String = "Can't restore enabled processes, using defaults. ".to_string() + &e.to_string();
                 MessageBoxW(HWND(0), to_pcwstr(&err).1, w!("Error"), MB_OK | MB_ICONERROR);
-                break;
-            }
+                None
+            });
+        // Default processes are only used on the first run
+        let (is_paused, enabled_keys, profile_key): (bool, Vec<String>, Option<String>) = match saved_state {
+            Some(state) => (state.paused, state.enabled, state.profile),
+            None => (false, settings.default_process.clone(), None),
+        };
+        MENU_STATE.init_random(random::Rng::entropy_seed());
+        MENU_STATE.init_active_profile(profile_key.and_then(|key| MENU_STATE.find_profile(&key)));
+        // A rotating profile starts with a fresh random part of its entries
+        let enabled_ids: Vec<MenuId> = MENU_STATE.init_rotation(std::time::Instant::now())
+            .unwrap_or_else(|| enabled_keys.iter().filter_map(|key| MENU_STATE.find(key)).collect());
+        // The restored state already reflects the schedules and any switching by hand,
+        // only windows opening or closing from now on change it
+        MENU_STATE.init_schedules(&SystemClock);
+        if is_paused {
+            MENU_STATE.init_paused(enabled_ids);
+        } else {
+            // Started by the launch timer, see launch_due
+            MENU_STATE.enable_staggered(&enabled_ids, std::time::Instant::now());
         }
-        #[cfg(feature = "logger")] debug!("Started default processes.");
+        #[cfg(feature = "logger")] debug!("Queued default processes.");
 
         let autostart = AUTOSTART.init()?;
         #[cfg(feature = "logger")] debug!("Autostart feature initialized.");
@@ -120,6 +241,9 @@ fn main() -> Result<()> {
         assert!(!icon_paused.is_invalid());
 
         TRAY_MENU_STATE.init(&MENU_STATE, autostart, icon_active, icon_paused)?;
+        if MENU_STATE.is_paused() {
+            TRAY_MENU_STATE.pause(autostart);
+        }
         #[cfg(feature = "logger")] debug!("Tray menu initialized.");
     }
 
@@ -166,6 +290,19 @@ fn main() -> Result<()> {
 
     #[cfg(feature = "logger")] debug!("Tray icon added.");
 
+    unsafe {
+        watch_config_files();
+        let timer: usize = SetTimer(win_handle, RELOAD_TIMER_ID, RELOAD_INTERVAL, None);
+        assert!(timer != 0);
+        let timer: usize = SetTimer(win_handle, RECONCILE_TIMER_ID, RECONCILE_INTERVAL, None);
+        assert!(timer != 0);
+        if MENU_STATE.is_launch_pending() {
+            let timer: usize = SetTimer(win_handle, LAUNCH_TIMER_ID, LAUNCH_INTERVAL, None);
+            assert!(timer != 0);
+        }
+    }
+    #[cfg(feature = "logger")] debug!("Config watcher, reconciler and launcher started.");
+
     // unsafe {
     //     ShowWindow(win_handle, SW_SHOW);
     // }
@@ -192,6 +329,7 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+#[cfg(windows)]
 fn icon_helper(win_handle: HWND, message: NOTIFY_ICON_MESSAGE) -> Result<()> {
     let icon: HICON = unsafe { TRAY_MENU_STATE.get_icon() };
     let mut tray_data: NOTIFYICONDATAW = NOTIFYICONDATAW {
@@ -219,6 +357,37 @@ fn icon_helper(win_handle: HWND, message: NOTIFY_ICON_MESSAGE) -> Result<()> {
     Ok(())
 }
 
+// Balloon notification next to the tray icon
+#[cfg(windows)]
+fn show_balloon(win_handle: HWND, title: &str, text: &str) -> Result<()> {
+    let mut tray_data: NOTIFYICONDATAW = NOTIFYICONDATAW {
+        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: win_handle,
+        uID: TRAY_ICON_ID,
+        uFlags: NIF_INFO,
+        dwInfoFlags: NIIF_WARNING,
+
+        ..Default::default()
+    };
+    copy_truncated(&mut tray_data.szInfoTitle, title);
+    copy_truncated(&mut tray_data.szInfo, text);
+
+    let result: BOOL = execute!(Shell_NotifyIconW(NIM_MODIFY, &tray_data))?;
+    assert!(result.as_bool());
+
+    Ok(())
+}
+
+// Fixed size string fields of Win32 structs, the last element stays 0
+#[cfg(windows)]
+fn copy_truncated(target: &mut [u16], text: &str) {
+    let max = target.len() - 1;
+    for (t, c) in target.iter_mut().zip(text.encode_utf16().take(max)) {
+        *t = c;
+    }
+}
+
+#[cfg(windows)]
 unsafe fn flip_menu_item<S>(state_keeper: &mut S, context_menu: HMENU, menu_item: MenuId) ->
 std::result::Result<(), <S as Switch>::ErrorType>
 where S: Switch {
@@ -226,14 +395,15 @@ where S: Switch {
 
     if is_active {
         state_keeper.disable(&menu_item)?;
-        CheckMenuItem(context_menu, menu_item as u32, MF_UNCHECKED.0);
+        CheckMenuItem(context_menu, menu_item.0, MF_UNCHECKED.0);
     } else {
         state_keeper.enable(&menu_item)?;
-        CheckMenuItem(context_menu, menu_item as u32, MF_CHECKED.0);
+        CheckMenuItem(context_menu, menu_item.0, MF_CHECKED.0);
     }
     Ok(())
 }
 
+#[cfg(windows)]
 fn notify_if_error<T>(res: &std::result::Result<(), T>, window: HWND, message: &str) -> LRESULT
 where T: std::fmt::Display {
     if let Err(e) = res {
@@ -245,10 +415,265 @@ where T: std::fmt::Display {
     LRESULT_SUCCESS
 }
 
+// Machine-wide settings go first, per-user ones override them
+#[cfg(windows)]
+fn settings_paths() -> [PathBuf; 2] {
+    CONFIG_FILES.with(|files| {
+        let files = files.borrow();
+        [
+            PathBuf::from(files.machine_folder.clone() + SETTINGS_FILE),
+            PathBuf::from(files.config_folder.clone() + SETTINGS_FILE),
+        ]
+    })
+}
+
+#[cfg(windows)]
+fn catalog_path() -> PathBuf {
+    CONFIG_FILES.with(|files| PathBuf::from(files.borrow().config_folder.clone() + CATALOG_FILE))
+}
+
+#[cfg(windows)]
+fn packs_path() -> PathBuf {
+    CONFIG_FILES.with(|files| PathBuf::from(files.borrow().machine_folder.clone() + PACKS_FOLDER))
+}
+
+#[cfg(windows)]
+fn incident_log_path() -> PathBuf {
+    CONFIG_FILES.with(|files| PathBuf::from(files.borrow().home_folder.clone() + INCIDENT_LOG_FILE))
+}
+
+#[cfg(windows)]
+fn state_path() -> PathBuf {
+    CONFIG_FILES.with(|files| PathBuf::from(files.borrow().config_folder.clone() + STATE_FILE))
+}
+
+// The packs folder itself is watched too, so new packs are noticed
+#[cfg(windows)]
+fn watch_config_files() {
+    let mut watched: Vec<PathBuf> = settings_paths().to_vec();
+    watched.push(catalog_path());
+    watched.push(packs_path());
+    for path in pack::find_packs(&packs_path()).unwrap_or_default() {
+        watched.push(pack::signature_path(&path));
+        watched.push(path);
+    }
+    CONFIG_FILES.with(|files| {
+        let watcher = &mut files.borrow_mut().watcher;
+        watcher.clear();
+        for path in watched {
+            watcher.watch(path);
+        }
+    });
+}
+
+#[cfg(windows)]
+fn load_settings(settings: &mut Settings) -> std::io::Result<Vec<String>> {
+    let paths = settings_paths();
+    settings.load(&paths[0], &paths[1])
+}
+
+#[cfg(windows)]
+unsafe fn report_settings_warnings(window: HWND, warnings: &[String]) {
+    if warnings.is_empty() {
+        return;
+    }
+    #[cfg(feature = "logger")] warn!("Settings: {0}", warnings.join("; "));
+    let text: String = "Suspicious settings:\n".to_string() + &warnings.join("\n");
+    MessageBoxW(window, to_pcwstr(&text).1, w!("Warning"), MB_OK | MB_ICONWARNING);
+}
+
+#[cfg(windows)]
+unsafe fn report_pack_problems(window: HWND, problems: &[String]) {
+    if problems.is_empty() {
+        return;
+    }
+    #[cfg(feature = "logger")] warn!("Signature packs: {0}", problems.join("; "));
+    let text: String = "Some signature pack entries were not loaded:\n".to_string() + &problems.join("\n");
+    MessageBoxW(window, to_pcwstr(&text).1, w!("Warning"), MB_OK | MB_ICONWARNING);
+}
+
+// Stubs of a previous session that crashed before its job object could take them down,
+// e.g. on systems where stubs can't be put into a job
+#[cfg(windows)]
+fn clean_up_leftover_stubs(settings: &Settings) {
+    let proc_folder = settings.home_folder.clone() + &settings.proc_folder;
+    let mut res = lifetime::kill_leftover_stubs(&proc_folder, menu_entry::is_stub_copy).map(|_killed| {
+        #[cfg(feature = "logger")] for image in &_killed {
+            debug!("Killed leftover stub {0}", image);
+        }
+    });
+    if res.is_ok() && !settings.keep_stub_copies {
+        res = menu_entry::remove_stub_copies(&proc_folder);
+    }
+    if let Err(e) = res {
+        let err: String = "Can't clean up stubs of the previous session. ".to_string() + &e.to_string();
+        unsafe { MessageBoxW(HWND(0), to_pcwstr(&err).1, w!("Error"), MB_OK | MB_ICONERROR) };
+    }
+}
+
+// Applies edited settings and catalog. If any of them is invalid, the running configuration stays.
+// Changed folders only apply to decoys started after the reload.
+#[cfg(windows)]
+unsafe fn reload_config(window: HWND) -> LRESULT {
+    #[cfg(feature = "logger")] debug!("Config files changed, reloading.");
+    let mut settings = Settings::default();
+    let warnings = match load_settings(&mut settings) {
+        Ok(warnings) => warnings,
+        Err(e) => {
+            #[cfg(feature = "logger")] error!("Can't reload settings: {0}", e);
+            return notify_if_error(&Err(e), window, "Can't reload settings, keeping the running configuration.");
+        }
+    };
+    let mut catalog = match Catalog::load(&catalog_path()) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            #[cfg(feature = "logger")] error!("Can't reload decoy catalog: {0}", e);
+            return notify_if_error(&Err(e), window, "Can't reload decoy catalog, keeping the running configuration.");
+        }
+    };
+    // Packs are checked after the watcher is updated, a pack added meanwhile triggers another reload
+    watch_config_files();
+    report_settings_warnings(window, &warnings);
+    let pack_problems = pack::merge_packs(&mut catalog, &packs_path(), &settings.trusted_keys);
+    report_pack_problems(window, &pack_problems);
+
+    let res = MENU_STATE.reload(&catalog, &settings);
+    // Incidents go to the new home folder, the log file stays where it was opened
+    CONFIG_FILES.with(|files| files.borrow_mut().home_folder = settings.home_folder.clone());
+    // Refresh even on error, the catalog is replaced anyway
+    let refreshed = TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART))
+        .map_err(windows::core::Error::into);
+    let res = res
+        .and(refreshed)
+        .and_then(|_| save_state());
+    #[cfg(feature = "logger")] debug!("Config reloaded.");
+    notify_if_error(&res, window, "Can't apply the new configuration.")
+}
+
+// Stubs report what happens to their windows over the status channel
+#[cfg(windows)]
+unsafe fn log_stub_events() {
+    for (_entry, _process, _event) in MENU_STATE.take_stub_events() {
+        #[cfg(feature = "logger")] info!("Stub {0} of {1} reported: {2}", _process, _entry, _event);
+    }
+}
+
+// A decoy killed by somebody else hints at malware looking for analysis tools
+#[cfg(windows)]
+unsafe fn report_unexpected_exits(window: HWND) {
+    let exits = MENU_STATE.take_unexpected_exits();
+    if exits.is_empty() {
+        return;
+    }
+    for _exit in &exits {
+        #[cfg(feature = "logger")] warn!("{0}", _exit.to_log_line());
+    }
+    let _res = incident::append_to_log(&incident_log_path(), &exits);
+    #[cfg(feature = "logger")] if let Err(e) = _res { error!("Can't write the incident log: {0}", e); }
+    let text: String = match exits.as_slice() {
+        [exit] => exit.to_notification(),
+        _ => {
+            let names: Vec<&str> = exits.iter().map(|e| e.process.as_str()).collect();
+            format!("{0} decoys were terminated by another process: {1}.", exits.len(), names.join(", "))
+        }
+    };
+    let _ = show_balloon(window, "Decoy terminated", &text);
+}
+
+// Brings back stubs of enabled entries that died. Failures are only logged, they would pop up
+// every second otherwise; the tray marks entries that keep failing.
+#[cfg(windows)]
+unsafe fn reconcile(window: HWND) -> LRESULT {
+    let degraded = MENU_STATE.get_degraded_entries();
+    for (_name, _e) in MENU_STATE.reconcile(std::time::Instant::now()) {
+        #[cfg(feature = "logger")] warn!("Can't restart or stop {0}: {1}", _name, _e);
+    }
+    report_unexpected_exits(window);
+    if MENU_STATE.get_degraded_entries() == degraded {
+        return LRESULT_SUCCESS;
+    }
+    let res = TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART));
+    notify_if_error(&res, window, "Can't update the tray menu.")
+}
+
+// Starts the queued entries whose turn has come, the timer stops once the queue is empty.
+// Entries that fail are no longer enabled, so the tray is refreshed.
+#[cfg(windows)]
+unsafe fn launch_due(window: HWND) -> LRESULT {
+    let launched = MENU_STATE.launch_due(std::time::Instant::now());
+    if !MENU_STATE.is_launch_pending() {
+        KillTimer(window, LAUNCH_TIMER_ID);
+        #[cfg(feature = "logger")] debug!("All queued processes started.");
+    }
+    if launched.is_ok() {
+        return LRESULT_SUCCESS;
+    }
+    let res = MENU_STATE.check_batch(&launched)
+        .and(TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART))
+            .map_err(windows::core::Error::into));
+    notify_if_error(&res, window, "Can't start some enabled processes.")
+}
+
+// Swaps an entry of the rotating profile when it is time. Failures are only logged like in reconcile,
+// the next rotation fills the gap.
+#[cfg(windows)]
+unsafe fn rotate(window: HWND) -> LRESULT {
+    let rotated = MENU_STATE.rotate(std::time::Instant::now());
+    if rotated.succeeded.is_empty() && rotated.failed.is_empty() {
+        return LRESULT_SUCCESS;
+    }
+    #[cfg(feature = "logger")] for id in &rotated.succeeded {
+        debug!("Rotated {0} {1}.", MENU_STATE.get_name(id), if MENU_STATE.is_enabled(id) { "in" } else { "out" });
+    }
+    #[cfg(feature = "logger")] for (id, e) in &rotated.failed {
+        warn!("Can't rotate {0}: {1}", MENU_STATE.get_name(id), e);
+    }
+    let res = TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART))
+        .map_err(windows::core::Error::into)
+        .and(save_state());
+    notify_if_error(&res, window, "Can't update the tray menu.")
+}
+
+// Enables and disables schedule-controlled entries whose time window opened or closed.
+// Failures are only logged like in reconcile.
+#[cfg(windows)]
+unsafe fn apply_schedules(window: HWND) -> LRESULT {
+    let applied = MENU_STATE.apply_schedules(&SystemClock, std::time::Instant::now());
+    if applied.succeeded.is_empty() && applied.failed.is_empty() {
+        return LRESULT_SUCCESS;
+    }
+    #[cfg(feature = "logger")] for id in &applied.succeeded {
+        debug!("Schedule turned {0} {1}.", MENU_STATE.get_name(id), if MENU_STATE.is_enabled(id) { "on" } else { "off" });
+    }
+    #[cfg(feature = "logger")] for (id, e) in &applied.failed {
+        warn!("Can't apply the schedule of {0}: {1}", MENU_STATE.get_name(id), e);
+    }
+    if MENU_STATE.is_launch_pending() {
+        SetTimer(window, LAUNCH_TIMER_ID, LAUNCH_INTERVAL, None);
+    }
+    let res = TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART))
+        .map_err(windows::core::Error::into)
+        .and(save_state());
+    notify_if_error(&res, window, "Can't update the tray menu.")
+}
+
+// Remembers enabled processes for the next start
+#[cfg(windows)]
+unsafe fn save_state() -> std::io::Result<()> {
+    let state = SavedState {
+        paused: MENU_STATE.is_paused(),
+        enabled: MENU_STATE.get_enabled_keys(),
+        profile: MENU_STATE.get_active_profile_key(),
+    };
+    state.save(&state_path())
+}
+
+#[cfg(windows)]
 unsafe fn get_menu_handle() -> HMENU {
     **TRAY_MENU_STATE
 }
 
+#[cfg(windows)]
 unsafe extern "system" fn wndproc(
     window: HWND,
     message: u32,
@@ -273,24 +698,34 @@ unsafe extern "system" fn wndproc(
         },
         WM_COMMAND => {
             #[cfg(feature = "logger")] debug!("WM_COMMAND command {0} {1}", LOWORD!(wparam), LOWORD!(lparam));
-            let lo_wparam: MenuId = FromPrimitive::from_u32(LOWORD!(wparam)).unwrap_or(MenuId::ERROR);
+            let lo_wparam: MenuId = MenuId(LOWORD!(wparam));
             match lo_wparam {
                 MenuId::PAUSE => {
                     TRAY_MENU_STATE.pause(AUTOSTART.is_enabled(&lo_wparam)); // Must go first
                     let res = icon_helper(window, NIM_MODIFY)
-                        .map_err(windows::core::Error::into)
-                        .and_then(
-                            |_| MENU_STATE.pause()
-                        );
+                        .map_err(windows::core::Error::into);
+                    let paused = MENU_STATE.pause();
+                    let res = res
+                        .and(MENU_STATE.check_batch(&paused))
+                        .and(save_state());
                     notify_if_error(&res, window, "Can't pause processes.")
                 }
                 MenuId::RESUME => {
                     TRAY_MENU_STATE.resume(AUTOSTART.is_enabled(&lo_wparam)); // Must go first
                     let res = icon_helper(window, NIM_MODIFY)
-                        .map_err(windows::core::Error::into)
-                        .and_then(
-                            |_| MENU_STATE.resume()
-                        );
+                        .map_err(windows::core::Error::into);
+                    let resumed = MENU_STATE.resume();
+                    // The menu still shows the entries that failed as checked
+                    let refreshed = if resumed.is_ok() {
+                        Ok(())
+                    } else {
+                        TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&lo_wparam))
+                            .map_err(windows::core::Error::into)
+                    };
+                    let res = res
+                        .and(MENU_STATE.check_batch(&resumed))
+                        .and(refreshed)
+                        .and(save_state());
                     notify_if_error(&res, window, "Can't resume processes.")
                 }
                 MenuId::AUTOSTART => {
@@ -298,68 +733,6 @@ unsafe extern "system" fn wndproc(
                     let res = flip_menu_item(&mut AUTOSTART, menu_handle, lo_wparam);
                     notify_if_error(&res, window, "Error when accessing registry.")
                 }
-                MenuId::GUEST
-                | MenuId::DEBUGGER
-                | MenuId::ANTIVIRUS
-                | MenuId::FIREWALL
-                | MenuId::TOOLS
-                => {
-                    // This should never happen. Assert?
-                    MessageBoxW(window, w!("Selected non-active menu items."), w!("Error"), MB_OK | MB_ICONERROR);
-                    LRESULT_SUCCESS
-                }
-                MenuId::GUEST_VIRTUALBOX
-                | MenuId::GUEST_VMWARE
-                | MenuId::GUEST_PARALLELS
-                | MenuId::GUEST_HYPERV
-                | MenuId::GUEST_VIRTUAL_PC
-                | MenuId::DEBUGGER_OLLY
-                | MenuId::DEBUGGER_WINDBG
-                | MenuId::DEBUGGER_X64DBG
-                | MenuId::DEBUGGER_IDA
-                | MenuId::DEBUGGER_IMMUNITY
-                | MenuId::DEBUGGER_RADARE2
-                | MenuId::DEBUGGER_BINARY_NINJA
-                // | MenuId::ANTIVIRUS_AVAST
-                | MenuId::ANTIVIRUS_AVIRA
-                // | MenuId::ANTIVIRUS_BITDEFENDER
-                // | MenuId::ANTIVIRUS_DRWEB
-                // | MenuId::ANTIVIRUS_ESET_NOD32
-                | MenuId::ANTIVIRUS_ESCAN
-                | MenuId::ANTIVIRUS_FORTINET
-                // | MenuId::ANTIVIRUS_FSECURE
-                | MenuId::ANTIVIRUS_GDATA
-                | MenuId::ANTIVIRUS_K7
-                // | MenuId::ANTIVIRUS_KASPERSKY
-                // | MenuId::ANTIVIRUS_MALWAREBYTES
-                | MenuId::ANTIVIRUS_MCAFEE
-                // | MenuId::ANTIVIRUS_NORTON
-                // | MenuId::ANTIVIRUS_PANDA
-                // | MenuId::ANTIVIRUS_SOPHOS
-                // | MenuId::ANTIVIRUS_TREND_MICRO
-                // | MenuId::ANTIVIRUS_WEBROOT
-                | MenuId::FIREWALL_COMODO
-                | MenuId::FIREWALL_GLASSWIRE
-                | MenuId::FIREWALL_TINYWALL
-                | MenuId::FIREWALL_ZONEALARM
-                | MenuId::TOOLS_PEID
-                | MenuId::TOOLS_RESOURCE_HACKER
-                | MenuId::TOOLS_DIE
-                | MenuId::TOOLS_DEBUG_VIEW
-                | MenuId::TOOLS_PROCESS_MONITOR
-                | MenuId::TOOLS_PROCESS_EXPLORER
-                | MenuId::TOOLS_TCPVIEW
-                | MenuId::TOOLS_WIRESHARK
-                | MenuId::TOOLS_PE_TOOLS
-                | MenuId::TOOLS_SPYXX
-                | MenuId::TOOLS_CTK_RES_EDIT
-                | MenuId::TOOLS_XN_RES_EDITOR
-                => {
-                    // TODO: Is there a nice way to bind this variable?
-                    let menu_handle = get_menu_handle();
-                    let res = flip_menu_item(&mut MENU_STATE, menu_handle, lo_wparam);
-                    notify_if_error(&res, window, "Can't finish your request.")
-                }
                 MenuId::ABOUT => {
                     let text = w!(
                         "Version: 1.3.0\n \
@@ -376,12 +749,64 @@ unsafe extern "system" fn wndproc(
                     SendMessageW(window, WM_CLOSE, WPARAM(0), LPARAM(0));
                     LRESULT_SUCCESS
                 }
-                MenuId::ERROR => {
+                id if MENU_STATE.contains(&id) => {
+                    // TODO: Is there a nice way to bind this variable?
+                    let menu_handle = get_menu_handle();
+                    // Manual changes detach the selection from the profile
+                    let detached = MENU_STATE.take_active_profile();
+                    if let Some(profile) = detached {
+                        CheckMenuItem(menu_handle, profile.0, MF_UNCHECKED.0);
+                    }
+                    let res = flip_menu_item(&mut MENU_STATE, menu_handle, id)
+                        .and_then(|_| save_state());
+                    // Entries of a scheduled profile lose their "(scheduled)" mark
+                    let res = match detached {
+                        Some(_) => res.and(TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&MenuId::AUTOSTART))
+                            .map_err(windows::core::Error::into)),
+                        None => res,
+                    };
+                    notify_if_error(&res, window, "Can't finish your request.")
+                }
+                id if MENU_STATE.is_profile(&id) => {
+                    let switched = MENU_STATE.switch_profile(&id);
+                    let res = MENU_STATE.check_batch(&switched);
+                    // Refresh even on error, some entries might have been switched already
+                    let refreshed = TRAY_MENU_STATE.refresh(&MENU_STATE, AUTOSTART.is_enabled(&id))
+                        .map_err(windows::core::Error::into);
+                    let res = res
+                        .and(refreshed)
+                        .and_then(|_| save_state());
+                    notify_if_error(&res, window, "Can't switch profile.")
+                }
+                id if MENU_STATE.is_category(&id) => {
+                    // This should never happen. Assert?
+                    MessageBoxW(window, w!("Selected non-active menu items."), w!("Error"), MB_OK | MB_ICONERROR);
+                    LRESULT_SUCCESS
+                }
+                _ => {
                     MessageBoxW(window, w!("Can't parse menu item. Please report to the developer."), w!("Error"), MB_ICONERROR);
                     LRESULT_SUCCESS
                 }
             }
         }
+        WM_TIMER if wparam.0 == RELOAD_TIMER_ID => {
+            log_stub_events();
+            if CONFIG_FILES.with(|files| files.borrow_mut().watcher.poll()) {
+                // Error boxes pump messages, don't reload again until this reload is done
+                KillTimer(window, RELOAD_TIMER_ID);
+                let res = reload_config(window);
+                SetTimer(window, RELOAD_TIMER_ID, RELOAD_INTERVAL, None);
+                res
+            } else {
+                LRESULT_SUCCESS
+            }
+        }
+        WM_TIMER if wparam.0 == RECONCILE_TIMER_ID => {
+            rotate(window);
+            apply_schedules(window);
+            reconcile(window)
+        }
+        WM_TIMER if wparam.0 == LAUNCH_TIMER_ID => launch_due(window),
         WM_PAINT => {
             #[cfg(feature = "logger")] debug!("WM_PAINT command {0} {1}", LOWORD!(wparam), LOWORD!(lparam));
             ValidateRect(window, None);
@@ -400,16 +825,18 @@ unsafe extern "system" fn wndproc(
     }
 }
 
+#[cfg(windows)]
 unsafe fn exit_routine() -> LRESULT {
     // https://learn.microsoft.com/en-us/windows/win32/learnwin32/closing-the-window
     TRAY_MENU_STATE.destroy();
     MENU_STATE.destroy();
     AUTOSTART.destroy();
-    HOME_FOLDER.clear();
+    CONFIG_FILES.with(|files| *files.borrow_mut() = ConfigFiles::new());
     PostQuitMessage(0); // This spawns WM_QUIT which terminates main loop
     LRESULT_SUCCESS
 }
 
+#[cfg(windows)]
 unsafe extern "system" fn handle_popup_menu(window: HWND, point: POINT, menu: HMENU) {
     SetForegroundWindow(window);
     TrackPopupMenu(
@@ -423,3 +850,8 @@ unsafe extern "system" fn handle_popup_menu(window: HWND, point: POINT, menu: HM
     );
     PostMessageW(window, WM_NULL, WPARAM(0), LPARAM(0));
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("des-resident runs on Windows only.");
+}
@@ -1,11 +1,16 @@
 use sha2::{Sha512, Digest};
 use std::io::ErrorKind;
-use std::{fs, io, process::Command, path::Path};
+use std::{fs, io, path::Path};
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::backoff::Backoff;
 use crate::release::{STUB_HASH, STUB_CONTENT};
-use crate::config::KEEP_STUB_COPIES;
-
-const PROC_FOLDER: &str = "proc/";
+use crate::catalog::Process;
+use crate::handshake::Persona;
+use crate::incident::{StubExit, UnexpectedExit};
+use crate::settings::{expand_env, Settings};
+use crate::status::StubEvent;
+use crate::stub_process::StubProcess;
 
 fn verify_file_hash(path: &str) -> Result<(), io::Error> {
     let mut file = fs::File::open(path)?;
@@ -19,66 +24,293 @@ fn verify_file_hash(path: &str) -> Result<(), io::Error> {
     Ok(())
 }
 
-pub struct MenuEntry<'u> {
-    entry_text: &'u str,
-    processes: Vec<(&'u str, Option<std::process::Child>)>,
+/// True if the file at `path` is a copy of the stub.
+#[must_use]
+pub fn is_stub_copy(path: &str) -> bool {
+    verify_file_hash(path).is_ok()
+}
+
+fn unexpected_exit(entry: &str, process: &Process, child: &StubProcess, code: u32) -> UnexpectedExit {
+    // The reconciler runs every second, detection time is close enough if Windows can't tell
+    let now = SystemTime::now();
+    let (started, exited) = child.lifetime().unwrap_or((now, now));
+    UnexpectedExit { entry: entry.to_string(), process: process.name.clone(), exit: StubExit::Code(code), started, exited }
+}
+
+// Writes or verifies the stub copy for `process` and starts it. Errors name the process.
+fn spawn_stub(proc_folder: &str, process: &Process) -> io::Result<StubProcess> {
+    let process_path: String = proc_folder.to_string() + process.name.as_str();
+    let spawn = || -> io::Result<StubProcess> {
+        let try_exist = Path::new(&process_path).try_exists();
+        if let Ok(true) = try_exist {
+            verify_file_hash(&process_path)?;
+        } else {
+            let res = fs::create_dir_all(proc_folder);
+            if let Err(e) = res {
+                if e.kind() != ErrorKind::AlreadyExists {
+                    return Err(e);
+                }
+            }
+            fs::write(&process_path, STUB_CONTENT)?;
+        }
+        // Without a template the stub looks like it was started from Explorer
+        let command_line: String = match &process.command_line {
+            Some(template) => expand_env(template, |var| std::env::var(var).ok(), |_| {}),
+            None => format!("\"{0}\"", process_path),
+        };
+        // The stub registers its window with these, or with its own defaults
+        let persona = Persona {
+            window_class: process.window_class.clone(),
+            window_title: process.window_title.clone(),
+        };
+        StubProcess::spawn(&process_path, &command_line, &persona)
+    };
+    spawn().map_err(|e| named(process, e))
+}
+
+// Waits until `deadline` for a stub asked to exit, kills it after that and removes its copy
+// unless copies are kept. Errors name the process.
+fn stop_stub(proc_folder: &str, keep_stub_copies: bool, process: &Process, child: &mut StubProcess, deadline: Instant)
+-> io::Result<()> {
+    let res = child.stop(deadline).and_then(|_| {
+        if keep_stub_copies {
+            return Ok(());
+        }
+        fs::remove_file(proc_folder.to_string() + process.name.as_str())
+    });
+    res.map_err(|e| named(process, e))
+}
+
+fn named(process: &Process, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{0}: {1}", process.name, e))
+}
+
+/// Removes the stub copies in `proc_folder`, e.g. left there by a session that crashed.
+/// Files that aren't stubs are left alone.
+pub fn remove_stub_copies(proc_folder: &str) -> io::Result<()> {
+    let files = match fs::read_dir(proc_folder) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for file in files {
+        let path = file?.path();
+        let path = path.to_string_lossy();
+        if is_stub_copy(&path) {
+            fs::remove_file(path.as_ref())?;
+        }
+    }
+    Ok(())
+}
+
+pub struct MenuEntry {
+    entry_text: String,
+    processes: Vec<(Process, Option<StubProcess>)>,
     is_active: bool,
     proc_folder: String,
+    keep_stub_copies: bool,
+    // How long stubs may take to exit on request
+    stop_timeout: Duration,
+    // Restarts of stubs that died while the entry was enabled
+    backoff: Backoff,
+    // Stubs that ended without being stopped, until somebody reports them
+    unexpected_exits: Vec<UnexpectedExit>,
 }
 
-impl <'u> MenuEntry<'u> {
-    pub fn new(text: &'u str, process_list: Vec<(&'u str, Option<std::process::Child>)>) -> MenuEntry<'u> {
-        let home_folder: String = unsafe { crate::HOME_FOLDER.clone() };
-        MenuEntry { entry_text: text, processes: process_list, is_active: false, proc_folder: home_folder + PROC_FOLDER }
+impl MenuEntry {
+    pub fn new(text: String, process_list: Vec<(Process, Option<StubProcess>)>, settings: &Settings) -> MenuEntry {
+        MenuEntry {
+            entry_text: text,
+            processes: process_list,
+            is_active: false,
+            proc_folder: settings.home_folder.clone() + &settings.proc_folder,
+            keep_stub_copies: settings.keep_stub_copies,
+            stop_timeout: Duration::from_millis(settings.stop_timeout_ms),
+            backoff: Backoff::new(),
+            unexpected_exits: Vec::new(),
+        }
     }
 
+    /// Starts the stubs of all processes of the entry. If one fails, the stubs started by this call
+    /// are stopped again and the error names the process that failed.
     pub fn start_process(&mut self) -> std::io::Result<()> {
-        for (process_name, process_child) in &mut self.processes {
+        let mut started: Vec<usize> = Vec::new();
+        let mut failure: Option<io::Error> = None;
+        for (i, (process, process_child)) in self.processes.iter_mut().enumerate() {
             if process_child.is_none() {
-                let process_path: String = self.proc_folder.clone() + process_name;
-                let try_exist = Path::new(&process_path).try_exists();
-                if let Ok(true) = try_exist {
-                    verify_file_hash(&process_path)?;
-                } else {
-                    let res = fs::create_dir_all(&self.proc_folder);
-                    if let Err(e) = &res {
-                        if e.kind() != ErrorKind::AlreadyExists {
-                            return res;
-                        }
+                match spawn_stub(&self.proc_folder, process) {
+                    Ok(child) => {
+                        *process_child = Some(child);
+                        started.push(i);
                     }
-                    fs::write(&process_path, STUB_CONTENT)?;
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(e) = failure {
+            #[cfg(feature = "logger")] warn!("Can't start {0}, rolling back: {1}", self.entry_text, e);
+            let deadline = Instant::now() + self.stop_timeout;
+            for i in started {
+                let (process, process_child) = &mut self.processes[i];
+                if let Some(mut child) = process_child.take() {
+                    // Best effort, the original error is the one to report
+                    let _ = child.request_exit();
+                    let _ = stop_stub(&self.proc_folder, self.keep_stub_copies, process, &mut child, deadline);
                 }
-                let c = Command::new(&process_path).arg("arg1").spawn()?;
-                *process_child = Some(c);
             }
+            return Err(e);
         }
         self.is_active = true;
         Ok(())
     }
 
+    /// Stops the stubs of all processes of the entry. Stubs are asked to exit first and killed
+    /// if they don't within the stop timeout. If one can't be stopped, the stubs stopped
+    /// by this call are started again, so the entry stays enabled as a whole.
     pub fn stop_process(&mut self) -> std::io::Result<()> {
-        for (process_name, process_child) in &mut self.processes {
-            if let Some(proc) = process_child {
-                proc.kill()?;
-                if !KEEP_STUB_COPIES {
-                    proc.wait()?;
-                    let process_path: String = self.proc_folder.clone() + process_name;
-                    fs::remove_file(process_path)?;
-                }
-                *process_child = None
+        self.request_stop();
+        self.finish_stop(Instant::now())
+    }
+
+    /// First half of `stop_process`: asks the running stubs to exit and returns right away.
+    /// Asking the stubs of several entries before finishing any lets them all shut down in parallel.
+    pub fn request_stop(&mut self) {
+        for (process, process_child) in &mut self.processes {
+            let Some(child) = process_child else { continue };
+            // Dead already, so it wasn't us
+            if let Ok(Some(code)) = child.try_wait() {
+                self.unexpected_exits.push(unexpected_exit(&self.entry_text, process, child, code));
+            } else {
+                // All stubs are asked first, so they shut down in parallel.
+                // A stub that can't be asked is killed once the timeout is over.
+                let _ = child.request_exit();
+            }
+        }
+    }
+
+    /// Second half of `stop_process`: gives the stubs the stop timeout counted from `requested`,
+    /// the time `request_stop` was called, then kills the ones still running.
+    pub fn finish_stop(&mut self, requested: Instant) -> std::io::Result<()> {
+        let deadline = requested + self.stop_timeout;
+        let mut stopped: Vec<usize> = Vec::new();
+        let mut failure: Option<io::Error> = None;
+        for (i, (process, process_child)) in self.processes.iter_mut().enumerate() {
+            let Some(child) = process_child else { continue };
+            if let Err(e) = stop_stub(&self.proc_folder, self.keep_stub_copies, process, child, deadline) {
+                failure = Some(e);
+                break;
+            }
+            *process_child = None;
+            stopped.push(i);
+        }
+        if let Some(e) = failure {
+            #[cfg(feature = "logger")] warn!("Can't stop {0}, rolling back: {1}", self.entry_text, e);
+            for i in stopped {
+                let (process, process_child) = &mut self.processes[i];
+                // The reconciler retries the ones that don't come back
+                *process_child = spawn_stub(&self.proc_folder, process).ok();
             }
+            return Err(e);
         }
         self.is_active = false;
+        self.backoff.reset();
         Ok(())
     }
 
+    /// Restarts stubs of an enabled entry that exited, stopped reporting or didn't confirm their start.
+    /// Restarts back off exponentially while the stubs keep dying. Returns true if stubs were restarted.
+    pub fn reconcile(&mut self, now: Instant) -> std::io::Result<bool> {
+        if !self.is_active {
+            return Ok(false);
+        }
+        let mut missing = false;
+        for (process, process_child) in &mut self.processes {
+            let Some(child) = process_child else {
+                missing = true;
+                continue;
+            };
+            if let Err(_e) = child.check_started() {
+                #[cfg(feature = "logger")] warn!("Stub {0} didn't start, killing it: {1}", process.name, _e);
+                child.kill()?;
+                child.wait()?;
+                *process_child = None;
+                missing = true;
+                continue;
+            }
+            match child.try_wait()? {
+                // Own kills remove the child right away, whatever is left here ended on its own or was killed by others
+                Some(code) => self.unexpected_exits.push(unexpected_exit(&self.entry_text, process, child, code)),
+                None if child.is_hung() => {
+                    #[cfg(feature = "logger")] warn!("Stub {0} stopped reporting, killing it.", process.name);
+                    child.kill()?;
+                    child.wait()?;
+                }
+                None => continue,
+            }
+            *process_child = None;
+            missing = true;
+        }
+        if !missing {
+            self.backoff.settle(now);
+            return Ok(false);
+        }
+        if !self.backoff.is_due(now) {
+            return Ok(false);
+        }
+        self.backoff.attempted(now);
+        self.start_process()?;
+        Ok(true)
+    }
+
+    /// True if the entry is enabled but its stubs keep dying.
+    #[must_use]
+    pub fn is_degraded(&self) -> bool {
+        self.is_active && self.backoff.is_degraded()
+    }
+
     #[must_use]
     pub fn is_process_active(&self) -> bool {
         self.is_active
     }
 
-    pub fn get_name(&self) -> &'u str {
-        self.entry_text
+    pub fn get_name(&self) -> &str {
+        &self.entry_text
+    }
+
+    /// Stubs that ended without the resident stopping them, since the last call.
+    pub fn take_unexpected_exits(&mut self) -> Vec<UnexpectedExit> {
+        std::mem::take(&mut self.unexpected_exits)
+    }
+
+    /// Events reported by the running stubs of this entry, with the process name of each stub.
+    pub fn take_stub_events(&self) -> Vec<(&str, StubEvent)> {
+        self.processes.iter()
+            .filter_map(|(process, child)| Some((process.name.as_str(), child.as_ref()?)))
+            .flat_map(|(name, child)| child.take_events().into_iter().map(move |e| (name, e)))
+            .collect()
+    }
+
+    /// Moves running processes of `old` into this entry if both run the same stubs, with the same windows,
+    /// from the same folder.
+    /// Returns false and leaves both entries untouched otherwise.
+    pub fn take_over(&mut self, old: &mut MenuEntry) -> bool {
+        let same_processes = self.processes.len() == old.processes.len()
+            && self.processes.iter().zip(&old.processes).all(|((new, _), (old, _))| new == old);
+        if !same_processes || self.proc_folder != old.proc_folder {
+            return false;
+        }
+        for ((_, new_child), (_, old_child)) in self.processes.iter_mut().zip(&mut old.processes) {
+            *new_child = old_child.take();
+        }
+        self.is_active = old.is_active;
+        // Stub copies were created under the old rules
+        self.keep_stub_copies = old.keep_stub_copies;
+        self.backoff = std::mem::take(&mut old.backoff);
+        old.is_active = false;
+        true
     }
 
     // pub fn refresh(&mut self) {
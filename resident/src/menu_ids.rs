@@ -1,73 +1,46 @@
-#[allow(non_camel_case_types)]
-#[allow(clippy::upper_case_acronyms)]
-#[derive(PartialOrd, PartialEq, Ord, Eq, Clone, Copy, FromPrimitive)]
-pub enum MenuId {
-    GUEST = 1,
-    GUEST_VIRTUALBOX,
-    GUEST_VMWARE,
-    GUEST_PARALLELS,
-    GUEST_HYPERV,
-    GUEST_VIRTUAL_PC,
-    DEBUGGER,
-    DEBUGGER_OLLY,
-    DEBUGGER_WINDBG,
-    DEBUGGER_X64DBG,
-    DEBUGGER_IDA,
-    DEBUGGER_IMMUNITY,
-    DEBUGGER_RADARE2,
-    DEBUGGER_BINARY_NINJA,
-    ANTIVIRUS,
-    // ANTIVIRUS_AVAST,
-    ANTIVIRUS_AVIRA,
-    // ANTIVIRUS_BITDEFENDER,
-    // ANTIVIRUS_DRWEB,
-    ANTIVIRUS_ESCAN,
-    // ANTIVIRUS_ESET_NOD32,
-    ANTIVIRUS_FORTINET,
-    // ANTIVIRUS_FSECURE,
-    ANTIVIRUS_GDATA,
-    ANTIVIRUS_K7,
-    // ANTIVIRUS_KASPERSKY,
-    // ANTIVIRUS_MALWAREBYTES,
-    ANTIVIRUS_MCAFEE,
-    // ANTIVIRUS_NORTON,
-    // ANTIVIRUS_PANDA,
-    // ANTIVIRUS_SOPHOS,
-    // ANTIVIRUS_TREND_MICRO,
-    // ANTIVIRUS_WEBROOT,
-    FIREWALL,
-    FIREWALL_COMODO,
-    FIREWALL_GLASSWIRE,
-    FIREWALL_TINYWALL,
-    FIREWALL_ZONEALARM,
+// Command id of a tray menu item. Fixed commands have reserved values,
+// catalog entries and categories get theirs from MenuIdRegistry at load time.
+#[derive(PartialOrd, PartialEq, Ord, Eq, Clone, Copy, Debug)]
+pub struct MenuId(pub u32);
 
-    TOOLS,
-    TOOLS_PEID,
-    TOOLS_RESOURCE_HACKER,
-    TOOLS_DIE,
-    TOOLS_DEBUG_VIEW,
-    TOOLS_PROCESS_MONITOR,
-    TOOLS_PROCESS_EXPLORER,
-    TOOLS_TCPVIEW,
-    TOOLS_WIRESHARK,
-    TOOLS_PE_TOOLS,
-    TOOLS_SPYXX,
-    TOOLS_CTK_RES_EDIT,
-    TOOLS_XN_RES_EDITOR,
-    // CFF explorer
-    // API monitor
-    // WinHex
-    // HxD
-    // Hiew
-    // Fiddler
-    // Scylla
-    // Ghidra
+#[cfg_attr(not(windows), allow(dead_code))]
+impl MenuId {
+    pub const AUTOSTART: MenuId = MenuId(1);
+    pub const ABOUT: MenuId = MenuId(2);
+    pub const EXIT: MenuId = MenuId(3);
+    pub const PAUSE: MenuId = MenuId(4);
+    pub const RESUME: MenuId = MenuId(5);
+}
+
+// Ids below this value are reserved for the fixed commands
+#[cfg_attr(not(windows), allow(dead_code))]
+const FIRST_DYNAMIC_ID: u32 = 100;
+// WM_COMMAND delivers the menu id in LOWORD of WPARAM
+#[cfg_attr(not(windows), allow(dead_code))]
+const LAST_DYNAMIC_ID: u32 = 0xFFFF;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct MenuIdRegistry {
+    next: u32,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl MenuIdRegistry {
+    pub const fn new() -> MenuIdRegistry {
+        MenuIdRegistry { next: FIRST_DYNAMIC_ID }
+    }
 
-    AUTOSTART,
-    ABOUT,
-    EXIT,
-    PAUSE,
-    RESUME,
+    /// Gives out the next free id, `None` if the id space is exhausted.
+    pub fn allocate(&mut self) -> Option<MenuId> {
+        if self.next > LAST_DYNAMIC_ID {
+            return None;
+        }
+        let id = MenuId(self.next);
+        self.next += 1;
+        Some(id)
+    }
 
-    ERROR,
+    pub fn reset(&mut self) {
+        self.next = FIRST_DYNAMIC_ID;
+    }
 }
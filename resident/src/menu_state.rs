@@ -1,400 +1,623 @@
+use crate::catalog::{Catalog, ProfileRotation};
+use crate::config::{LAUNCH_JITTER_MS, MAX_CONCURRENT_LAUNCHES};
+use crate::settings::Settings;
+use crate::incident::UnexpectedExit;
+use crate::launch::LaunchQueue;
 use crate::menu_entry::*;
-use crate::menu_ids::MenuId;
-use crate::switch::Switch;
+use crate::menu_ids::{MenuId, MenuIdRegistry};
+use crate::random::Rng;
+use crate::rotation::Rotation;
+use crate::schedule::{Clock, Schedule, Scheduler};
+use crate::status::StubEvent;
+use crate::switch::{BatchResult, Switch};
 
 use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
-pub struct MenuState<'a> {
-    m: BTreeMap<MenuId, MenuEntry<'a>>,
+// Tray submenu built from a catalog category
+pub struct MenuCategory {
+    pub id: MenuId,
+    pub name: String,
+    pub entries: Vec<MenuId>,
+}
+
+// Tray item that switches to a catalog profile
+pub struct MenuProfile {
+    pub id: MenuId,
+    pub key: String,
+    pub name: String,
+    pub entries: Vec<MenuId>,
+    pub rotation: Option<ProfileRotation>,
+    pub schedule: Option<Schedule>,
+}
+
+pub struct MenuState {
+    m: BTreeMap<MenuId, MenuEntry>,
+    keys: BTreeMap<String, MenuId>,
+    categories: Vec<MenuCategory>,
+    profiles: Vec<MenuProfile>,
+    active_profile: Option<MenuId>,
+    id_registry: MenuIdRegistry,
     is_paused: bool,
     paused_process_list: Vec<MenuId>,
+    // Enabled entries whose stubs aren't started yet, see enable_staggered
+    launches: LaunchQueue,
+    rng: Rng,
+    // Rotation of the active profile, if it has one
+    rotation: Option<Rotation>,
+    // Entries with their own schedule, see apply_schedules
+    entry_schedules: BTreeMap<MenuId, Schedule>,
+    scheduler: Scheduler,
+    // Entries a reload removed while their stubs couldn't be stopped, reconcile tries again
+    retired: Vec<MenuEntry>,
 }
 
-impl Switch for MenuState<'_> {
+impl Switch for MenuState {
     type ErrorType = std::io::Error;
 
     fn enable(&mut self, id: &MenuId) -> std::io::Result<()> {
         let menu_entry = self.m.get_mut(id).ok_or(std::io::ErrorKind::NotFound)?;
+        // Asked for explicitly, no reason to wait for its turn
+        self.launches.remove(id);
         menu_entry.start_process()
     }
 
     fn disable(&mut self, id: &MenuId) -> std::io::Result<()> {
         let menu_entry = self.m.get_mut(id).ok_or(std::io::ErrorKind::NotFound)?;
+        self.launches.remove(id);
         menu_entry.stop_process()
     }
 
     #[must_use]
     fn is_enabled(&self, id: &MenuId) -> bool {
         match self.m.get(id) {
-            Some(v) => v.is_process_active(),
+            Some(v) => v.is_process_active() || self.launches.contains(id),
             None => {
                 // log the error
-                panic!("Key {0} doesn't exist in the map.", *id as u32)
+                panic!("Key {0} doesn't exist in the map.", id.0)
             },
         }
     }
+
+    // The stubs of all entries are asked to exit first and share one deadline,
+    // so stopping many entries takes one stop timeout rather than one per entry
+    fn disable_all(&mut self, menu_items: &[MenuId]) -> BatchResult<std::io::Error> {
+        for id in menu_items {
+            self.launches.remove(id);
+            if let Some(menu_entry) = self.m.get_mut(id) {
+                menu_entry.request_stop();
+            }
+        }
+        let requested = Instant::now();
+        let mut result = BatchResult::new();
+        for id in menu_items {
+            let res = match self.m.get_mut(id) {
+                Some(menu_entry) => menu_entry.finish_stop(requested),
+                None => Err(std::io::ErrorKind::NotFound.into()),
+            };
+            result.add(*id, res);
+        }
+        result
+    }
 }
 
-impl <'m> MenuState<'m> {
-    pub const fn new() -> MenuState<'m> {
-        MenuState { m: BTreeMap::new(), is_paused: false, paused_process_list: Vec::new() }
+impl MenuState {
+    pub const fn new() -> MenuState {
+        MenuState {
+            m: BTreeMap::new(),
+            keys: BTreeMap::new(),
+            categories: Vec::new(),
+            profiles: Vec::new(),
+            active_profile: None,
+            id_registry: MenuIdRegistry::new(),
+            is_paused: false,
+            paused_process_list: Vec::new(),
+            launches: LaunchQueue::new(Duration::from_millis(LAUNCH_JITTER_MS), MAX_CONCURRENT_LAUNCHES),
+            rng: Rng::new(0),
+            rotation: None,
+            entry_schedules: BTreeMap::new(),
+            scheduler: Scheduler::new(),
+            retired: Vec::new(),
+        }
     }
 
-    fn stop_all_running_processes(&mut self) -> std::io::Result<()> {
+    fn stop_all_running_processes(&mut self) -> BatchResult<std::io::Error> {
         let active_process_list = self.get_active_process_list();
-        self.stop_running_processes(&active_process_list)
+        self.disable_all(&active_process_list)
+    }
+
+    #[must_use]
+    pub fn contains(&self, key: &MenuId) -> bool {
+        self.m.contains_key(key)
+    }
+
+    #[must_use]
+    pub fn is_category(&self, key: &MenuId) -> bool {
+        self.categories.iter().any(|c| c.id == *key)
+    }
+
+    #[must_use]
+    pub fn is_profile(&self, key: &MenuId) -> bool {
+        self.profiles.iter().any(|p| p.id == *key)
+    }
+
+    #[must_use]
+    pub fn get_profiles(&self) -> &[MenuProfile] {
+        &self.profiles
+    }
+
+    #[must_use]
+    pub fn get_active_profile(&self) -> Option<MenuId> {
+        self.active_profile
+    }
+
+    /// Looks up the menu id of the profile `key`.
+    #[must_use]
+    pub fn find_profile(&self, key: &str) -> Option<MenuId> {
+        self.profiles.iter().find(|p| p.key == key).map(|p| p.id)
+    }
+
+    #[must_use]
+    pub fn get_active_profile_key(&self) -> Option<String> {
+        let active = self.active_profile?;
+        self.profiles.iter().find(|p| p.id == active).map(|p| p.key.clone())
+    }
+
+    /// Forgets the active profile, e.g. after the user flipped an entry manually.
+    /// Returns the profile that was active. Its rotation stops, the entries are under manual control now.
+    pub fn take_active_profile(&mut self) -> Option<MenuId> {
+        self.rotation = None;
+        self.active_profile.take()
     }
 
-    fn stop_running_processes(&mut self, process_list: &Vec<MenuId>) -> std::io::Result<()> {
-        for id in process_list {
-            self.disable(id)?;
+    /// Enables exactly the entries of the profile `id`, or a random part of them if the profile rotates.
+    /// Only entries whose state changes are stopped or started, each of them is attempted.
+    pub fn switch_profile(&mut self, id: &MenuId) -> BatchResult<std::io::Error> {
+        let mut result = BatchResult::new();
+        let Some(profile) = self.profiles.iter().find(|p| p.id == *id) else {
+            result.add(*id, Err(std::io::ErrorKind::NotFound.into()));
+            return result;
+        };
+        self.rotation = profile.rotation.map(|config| Rotation::new(profile.entries.clone(), &config));
+        let profile_schedule = profile.schedule.clone();
+        let target = match &mut self.rotation {
+            Some(rotation) => rotation.pick(&mut self.rng, Instant::now()),
+            None => profile.entries.clone(),
+        };
+        // Entries outside of their time window would be stopped by the next schedule check anyway
+        let target: Vec<MenuId> = target.into_iter()
+            .filter(|id| self.is_allowed_now(id, profile_schedule.as_ref()))
+            .collect();
+        if self.is_paused {
+            // Takes effect on resume
+            self.paused_process_list = target;
+        } else {
+            let to_stop: Vec<MenuId> = self.get_active_process_list().into_iter()
+                .filter(|m| !target.contains(m))
+                .collect();
+            result = self.disable_all(&to_stop);
+            let to_start: Vec<MenuId> = target.into_iter()
+                .filter(|m| !self.is_enabled(m))
+                .collect();
+            result.merge(self.enable_all(&to_start));
         }
-        Ok(())
+        self.active_profile = Some(*id);
+        result
+    }
+
+    /// Turns the failures of a batch into one error that names every failed entry.
+    pub fn check_batch(&self, result: &BatchResult<std::io::Error>) -> std::io::Result<()> {
+        let name = |id: &MenuId| -> String {
+            match self.m.get(id) {
+                Some(me) => me.get_name().to_string(),
+                None => format!("Menu item {0}", id.0),
+            }
+        };
+        match result.summary(name) {
+            Some(summary) => Err(std::io::Error::other(summary)),
+            None => Ok(()),
+        }
+    }
+
+    /// Looks up the menu id of the catalog entry `key`.
+    #[must_use]
+    pub fn find(&self, key: &str) -> Option<MenuId> {
+        self.keys.get(key).copied()
+    }
+
+    #[must_use]
+    pub fn get_categories(&self) -> &[MenuCategory] {
+        &self.categories
     }
 
     #[must_use]
-    pub fn get_name(&self, key: &MenuId) -> &'m str {
+    pub fn get_name(&self, key: &MenuId) -> &str {
         match self.m.get(key) {
             Some(v) => v.get_name(),
             None => {
                 // log the error
-                panic!("Key {0} doesn't exist in the map.", *key as u32)
+                panic!("Key {0} doesn't exist in the map.", key.0)
             },
         }
     }
 
+    /// Restarts stubs of enabled entries that died and stops entries removed by a reload that
+    /// couldn't be stopped then. Returns the entries that failed, a failing entry doesn't keep
+    /// the others from being handled.
+    pub fn reconcile(&mut self, now: Instant) -> Vec<(String, std::io::Error)> {
+        let mut errors: Vec<(String, std::io::Error)> = Vec::new();
+        for me in &mut self.retired {
+            me.request_stop();
+        }
+        let requested = Instant::now();
+        self.retired.retain_mut(|me| match me.finish_stop(requested) {
+            Ok(()) => false,
+            Err(e) => {
+                errors.push((me.get_name().to_string(), e));
+                true
+            }
+        });
+        for me in self.m.values_mut() {
+            match me.reconcile(now) {
+                Ok(_restarted) => {
+                    #[cfg(feature = "logger")] if _restarted { info!("Restarted stubs of {0}.", me.get_name()); }
+                }
+                Err(e) => errors.push((me.get_name().to_string(), e)),
+            }
+        }
+        errors
+    }
+
+    /// Stubs of all entries that ended without being stopped, since the last call.
+    pub fn take_unexpected_exits(&mut self) -> Vec<UnexpectedExit> {
+        self.m.values_mut().flat_map(|me| me.take_unexpected_exits()).collect()
+    }
+
+    /// Enabled entries whose stubs keep dying.
+    #[must_use]
+    pub fn get_degraded_entries(&self) -> Vec<MenuId> {
+        self.m.iter().filter(|(_, me)| me.is_degraded()).map(|(id, _)| *id).collect()
+    }
+
+    #[must_use]
+    pub fn is_degraded(&self, id: &MenuId) -> bool {
+        self.m.get(id).is_some_and(|me| me.is_degraded())
+    }
+
+    /// Events reported by all running stubs, as (entry name, process name, event).
+    pub fn take_stub_events(&self) -> Vec<(String, String, StubEvent)> {
+        self.m.values()
+            .flat_map(|me| me.take_stub_events().into_iter()
+                .map(move |(process, event)| (me.get_name().to_string(), process.to_string(), event)))
+            .collect()
+    }
+
+    // Entries waiting in the launch queue count as enabled
     #[must_use]
     fn get_active_process_list(&self) -> Vec<MenuId> {
         let mut active_process_list: Vec<MenuId> = Vec::new();
         for (id, me) in &self.m {
-            if me.is_process_active() {
+            if me.is_process_active() || self.launches.contains(id) {
                 active_process_list.push(*id);
             }
         }
         active_process_list
     }
 
-    pub fn pause(&mut self) -> std::io::Result<()> {
+    /// Stops all running entries and remembers them for resume. Entries that can't be stopped keep running.
+    pub fn pause(&mut self) -> BatchResult<std::io::Error> {
+        if self.is_paused {
+            return BatchResult::new();
+        }
+        let active_processes = self.get_active_process_list();
+        self.paused_process_list.clone_from(&active_processes);
+        self.is_paused = true;
+        self.disable_all(&active_processes)
+    }
+
+    /// Starts the entries that were running before the pause, each of them is attempted.
+    pub fn resume(&mut self) -> BatchResult<std::io::Error> {
         if !self.is_paused {
-            let active_processes = self.get_active_process_list();
-            self.paused_process_list.clone_from(&active_processes);
-            self.stop_running_processes(&active_processes)?;
-            self.is_paused = true;
+            return BatchResult::new();
         }
-        Ok(())
+        let process_to_resume = std::mem::take(&mut self.paused_process_list);
+        self.is_paused = false;
+        self.enable_all(&process_to_resume)
+    }
+
+    /// Enables `ids` a few at a time with random pauses in between, `launch_due` starts them.
+    /// Until then they count as enabled.
+    pub fn enable_staggered(&mut self, ids: &[MenuId], now: Instant) {
+        let ids: Vec<MenuId> = ids.iter()
+            .filter(|id| self.m.get(id).is_some_and(|me| !me.is_process_active()))
+            .copied()
+            .collect();
+        self.launches.schedule(&ids, now, &mut self.rng);
+    }
+
+    /// Starts the queued entries that are due at `now`.
+    pub fn launch_due(&mut self, now: Instant) -> BatchResult<std::io::Error> {
+        let due = self.launches.take_due(now);
+        self.enable_all(&due)
+    }
+
+    #[must_use]
+    pub fn is_launch_pending(&self) -> bool {
+        !self.launches.is_empty()
+    }
+
+    /// Starts the rotation of the active profile, if it has one, and returns the random part
+    /// of its entries to enable.
+    pub fn init_rotation(&mut self, now: Instant) -> Option<Vec<MenuId>> {
+        self.rotation = self.new_rotation();
+        let rotation = self.rotation.as_mut()?;
+        Some(rotation.pick(&mut self.rng, now))
+    }
+
+    /// Swaps one entry of the rotating profile for another when it is time. Nothing happens
+    /// while paused or without a rotating profile.
+    pub fn rotate(&mut self, now: Instant) -> BatchResult<std::io::Error> {
+        if self.is_paused || !self.rotation.as_ref().is_some_and(|r| r.is_due(now)) {
+            return BatchResult::new();
+        }
+        let Some(mut rotation) = self.rotation.take() else { return BatchResult::new() };
+        let mut rng = self.rng.clone();
+        let result = rotation.rotate(self, &mut rng, now);
+        self.rng = rng;
+        self.rotation = Some(rotation);
+        result
+    }
+
+    fn new_rotation(&self) -> Option<Rotation> {
+        let profile = self.profiles.iter().find(|p| Some(p.id) == self.active_profile)?;
+        profile.rotation.map(|config| Rotation::new(profile.entries.clone(), &config))
     }
 
-    pub fn resume(&mut self) -> std::io::Result<()> {
+    /// Enables and disables the schedule-controlled entries whose time window opened or closed
+    /// since the last call. Opened entries are queued like at startup and count as succeeded.
+    /// Nothing happens while paused, missed changes are applied on the first call after resume.
+    pub fn apply_schedules<C: Clock>(&mut self, clock: &C, now: Instant) -> BatchResult<std::io::Error> {
         if self.is_paused {
-            let process_to_resume = self.paused_process_list.clone();
-            for id in &process_to_resume {
-                self.enable(id)?;
-            }
-            self.paused_process_list.clear();
-            self.is_paused = false;
+            return BatchResult::new();
         }
-        Ok(())
+        let schedules = self.get_schedules();
+        let list: Vec<(MenuId, &Schedule)> = schedules.iter().map(|(id, s)| (*id, s)).collect();
+        let changes = self.scheduler.changes(&list, clock);
+        let opened: Vec<MenuId> = changes.iter().filter(|(_, open)| *open).map(|(id, _)| *id).collect();
+        let closed: Vec<MenuId> = changes.iter().filter(|(_, open)| !*open).map(|(id, _)| *id).collect();
+        self.enable_staggered(&opened, now);
+        let mut result = BatchResult::new();
+        for id in opened {
+            result.add(id, Ok(()));
+        }
+        result.merge(self.disable_all(&closed));
+        result
+    }
+
+    /// Takes the current time windows as the starting point without enabling or disabling anything,
+    /// so the state restored at startup stands until a window opens or closes.
+    pub fn init_schedules<C: Clock>(&mut self, clock: &C) {
+        let schedules = self.get_schedules();
+        let list: Vec<(MenuId, &Schedule)> = schedules.iter().map(|(id, s)| (*id, s)).collect();
+        self.scheduler.seed(&list, clock);
+    }
+
+    // Schedule-controlled entries with their schedules
+    fn get_schedules(&self) -> Vec<(MenuId, Schedule)> {
+        self.m.keys()
+            .filter_map(|id| self.schedule_of(id).map(|s| (*id, s.clone())))
+            .collect()
+    }
+
+    /// True if the entry is enabled and disabled by a schedule, its own or the active profile's.
+    #[must_use]
+    pub fn is_scheduled(&self, id: &MenuId) -> bool {
+        self.schedule_of(id).is_some()
+    }
+
+    // Own schedule of the entry, or the one of the active profile if the entry belongs to it
+    fn schedule_of(&self, id: &MenuId) -> Option<&Schedule> {
+        self.entry_schedules.get(id).or_else(|| {
+            let profile = self.profiles.iter().find(|p| Some(p.id) == self.active_profile)?;
+            profile.schedule.as_ref().filter(|_| profile.entries.contains(id))
+        })
+    }
+
+    // False if the entry is outside of its time window at the last schedule check
+    fn is_allowed_now(&self, id: &MenuId, profile_schedule: Option<&Schedule>) -> bool {
+        let Some(time) = self.scheduler.last_time() else { return true };
+        match self.entry_schedules.get(id).or(profile_schedule) {
+            Some(schedule) => schedule.is_active(time),
+            None => true,
+        }
+    }
+
+    /// Seeds the random decisions, like launch pauses and rotations. Tests pass a fixed seed.
+    pub fn init_random(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn init_active_profile(&mut self, id: Option<MenuId>) {
+        self.active_profile = id;
+    }
+
+    /// Marks the state as paused without starting anything; `process_list` is started on resume.
+    pub fn init_paused(&mut self, process_list: Vec<MenuId>) {
+        self.paused_process_list = process_list;
+        self.is_paused = true;
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Keys of the entries that are enabled now, or will be enabled on resume.
+    #[must_use]
+    pub fn get_enabled_keys(&self) -> Vec<String> {
+        let enabled: Vec<MenuId> = if self.is_paused {
+            self.paused_process_list.clone()
+        } else {
+            self.get_active_process_list()
+        };
+        self.keys.iter()
+            .filter(|(_, id)| enabled.contains(id))
+            .map(|(key, _)| key.clone())
+            .collect()
     }
 
     pub fn destroy(&mut self) {
+        for me in &mut self.retired {
+            me.request_stop();
+        }
+        let requested = Instant::now();
         let _ignored = self.stop_all_running_processes();
+        for me in &mut self.retired {
+            let _ignored = me.finish_stop(requested);
+        }
+        self.retired.clear();
         self.m.clear();
+        self.keys.clear();
+        self.categories.clear();
+        self.profiles.clear();
+        self.active_profile = None;
+        self.rotation = None;
+        self.entry_schedules.clear();
+        self.scheduler = Scheduler::new();
+        self.id_registry.reset();
         self.paused_process_list.clear();
     }
 
-    pub fn init_menu_entries(&mut self) {
-        self.m.insert(MenuId::GUEST_VIRTUALBOX, MenuEntry::new(
-            "VirtualBox",
-            vec![
-                ("VBoxTray.exe", None),    // VirtualBox Guest Additions Tray Application
-                ("VBoxService.exe", None), // VirtualBox Guest Additions Service
-            ])
-        );
-        self.m.insert(MenuId::GUEST_VMWARE, MenuEntry::new(
-            "VMware",
-            vec![
-                ("vmacthlp.exe", None),    // VMware Activation Helper
-                ("vmtoolsd.exe", None),    // VMware Tools Core Service
-                ("vmwaretray.exe", None),  // VMware Tools tray application
-                ("vmware-tray.exe", None), // VMware Tray Process
-                ("VMwareUser.exe", None),  // VMware Tools Service
-            ])
-        );
-        self.m.insert(MenuId::GUEST_PARALLELS, MenuEntry::new(
-            "Parallels",
-            vec![
-                ("prl_cc.exe", None),        // Parallels Control Center
-                ("prl_tools.exe", None),     // Parallels Tools
-                ("SharedIntApp.exe", None),  // Parallels Server/Desktop (runtime switch)
-            ])
-        );
-        self.m.insert(MenuId::GUEST_HYPERV, MenuEntry::new(
-            "Hyper-V",
-            vec![
-                ("VmComputeAgent.exe", None), // Hyper-V Guest Compute Service
-            ])
-        );
-        self.m.insert(MenuId::GUEST_VIRTUAL_PC, MenuEntry::new(
-            "Windows Virtual PC",
-            vec![
-                ("vmusrvc.exe", None),  // Virtual Machine User Services
-                ("vmsrvc.exe", None),   // Virtual Machine Services
-            ])
-        );
-        self.m.insert(MenuId::DEBUGGER_OLLY, MenuEntry::new(
-            "OllyDBG",
-            vec![("ollydbg.exe", None)]
-        ));
-        self.m.insert(MenuId::DEBUGGER_WINDBG, MenuEntry::new(
-            "WinDBG",
-            vec![
-                ("windbg.exe", None),
-                // ("dbgsrv.exe", None),
-                ("usbview.exe", None),
-                ("logviewer.exe", None),
-            ])
-        );
-        self.m.insert(MenuId::DEBUGGER_X64DBG, MenuEntry::new(
-            "x64dbg",
-            vec![("x64dbg.exe", None)]
-        ));
-        self.m.insert(MenuId::DEBUGGER_IDA, MenuEntry::new(
-            "IDA Pro",
-            vec![("ida64.exe", None)]
-        ));
-        self.m.insert(MenuId::DEBUGGER_IMMUNITY, MenuEntry::new(
-            "Immunity",
-            vec![("ImmunityDebugger.exe", None)]
-        ));
-        self.m.insert(MenuId::DEBUGGER_RADARE2, MenuEntry::new(
-            "Radare 2",
-            vec![("iaito.exe", None)]
-        ));
-        self.m.insert(MenuId::DEBUGGER_BINARY_NINJA, MenuEntry::new(
-            "Binary ninja",
-            vec![("binaryninja.exe", None)]
-        ));
-        self.m.insert(MenuId::ANTIVIRUS_AVIRA, MenuEntry::new(
-            "Avira",
-            vec![
-                ("Avira.OptimizerHost.exe", None), // Avira Optimizer Host
-                ("Avira Safe Shopping.exe", None), // Avira Safe Shopping add-on for browsers
-                ("Avira.ServiceHost.exe", None),   // Avira Service Host
-                ("Avira.SoftwareUpdater.ServiceHost.exe", None), // Avira Updater Service Host
-                ("Avira.Spotlight.Service.exe", None),
-                ("Avira.Systray.exe", None),       // Avira Launcher
-                ("Avira.SystrayStartTrigger.exe", None), // Avira System Tray Service Start Trigger
-                ("Avira.VpnService.exe", None),    // Avira Phantom VPN
-                ("Avira.WebAppHost.exe", None),    // Avira Phantom VPN or WebAppHost
-                ("ProtectedService.exe", None),    // Avira Protected Antimalware Service
-                ("avscan.exe", None),              // Avira OnDemand File Scanner
-                ("toastnotifier.exe", None),       // AVToastNotifier
-                ("avupdate.exe", None),            // Updater for Avira products
-                ("ipmgui.exe", None),              // In Product Messaging Application
-                ("avgnt.exe", None),               // Avira AntiVir Guard Notification Tray
-            ])
-        );
-        self.m.insert(MenuId::ANTIVIRUS_ESCAN, MenuEntry::new(
-            "eScan",
-            vec![
-                ("avpmapp.exe", None),  // eScan File Monitoring System
-                ("econceal.exe", None), // eConceal Service
-                ("escanmon.exe", None), // eScan Monitoring Tray
-                ("escanpro.exe", None), // eScan Protection Center
-                ("avpMWrap.exe", None), // eScan Antivirus Suite
-                ("eScanRAD.exe", None), // eScan Remote Administration
-                ("MAILDISP.EXE", None), // eScan Mail Scanner Component
-                ("traycser.exe", None), // eScan Client Updater
-                ("trayeser.exe", None), // eScan Management Console
-                ("TRAYICOC.EXE", None), // eScan Client updater
-                ("TRAYICOS.EXE", None), // eScan Server updater
-                ("traysser.exe", None), // Service Module for eScan Server updater
-                ("consctl.exe", None),  // eScan Application Blocker
-                ("mwagent.exe", None),  // eScan Agent Application or MicroWorld Agent
-            ])
-        );
-        self.m.insert(MenuId::ANTIVIRUS_FORTINET, MenuEntry::new(
-            "Fortinet",
-            vec![
-                // https://docs.fortinet.com/document/forticlient/7.0.7/administration-guide/209271/forticlient-windows-processes
-                ("FCVbltScan.exe", None),  // FortiClient Vulnerability Scan Daemon
-                ("FortiAvatar.exe", None), // FortiClient User Avatar Agent
-                ("FortiClient.exe", None), // FortiClient Console
-                ("fcappdb.exe", None),     // FortiClient Application Database Service
-                ("fcaptmon.exe", None),    // FortiClient Sandbox Agent
-                ("FCDBLog.exe", None),     // FortiClient Logging Daemon
-                ("FCHelper64.exe", None),  // FortiClient System Helper
-                ("fmon.exe", None),        // FortiClient Realtime AntiVirus Protection
-                ("fortiae.exe", None),     // FortiClient Anti-Exploit
-                ("FortiESNAC.exe", None),  // FortiClient Network Access Control
-                ("fortifws.exe", None),    // FortiClient Firewall Service
-                ("FortiProxy.exe", None),  // FortiClient Proxy Service
-                ("FortiScand.exe", None),  // FortiClient Scan Server
-                ("FortiSettings.exe", None), // FortiClient Settings Service
-                ("FortiSSLVPNdaemon.exe", None), // FortiClient SSLVPN daemon
-                ("FortiTray.exe", None),   // FortiClient System Tray Controller
-                ("FortiUSBmon.exe", None), // FortiClient USB monitor protection
-                ("FortiWF.exe", None),     // FortiClient Web Filter Service
-            ])
-        );
-        self.m.insert(MenuId::ANTIVIRUS_GDATA, MenuEntry::new(
-            "G Data",
-            vec![
-                ("AVK.exe", None),        // G Data AntiVirus UI
-                ("AVKWCtlx64.exe", None), // G Data Filesystem Monitor Service
-                ("GdBgInx64.exe", None),  // G Data AntiVirus Bankguard
-                ("AVKProxy.exe", None),   // G Data AntiVirus Proxy Service
-                ("GDScan.exe", None),     // G Data AntiVirus Scan Server
-                ("AVKService.exe", None), // G Data InternetSecurity Scheduler Service
-                ("AVKTray.exe", None),    // G DATA InternetSecurity Tray Application
-                ("GDSC.exe", None),       // G DATA SecurityCenter
-                ("GDKBFltExe32.exe", None),
-            ])
-        );
-        self.m.insert(MenuId::ANTIVIRUS_K7, MenuEntry::new(
-            "K7",
-            vec![
-                ("K7RTScan.exe", None),    // K7 RealTime AntiVirus Services
-                ("K7FWSrvc.exe", None),    // K7 Firewall Services
-                ("K7PSSrvc.exe", None),    // K7 Privacy Manager
-                ("K7EmlPxy.exe", None),    // K7 EMail Proxy Server
-                ("K7TSecurity.exe", None), // K7 User Agent
-                ("K7AVScan.exe", None),    // K7 AntiVirus Scanner Loader
-                ("K7CrvSvc.exe", None),    // K7 Carnivore Service
-                ("K7SysMon.exe", None),    // K7 System Monitor
-                ("K7TSMain.exe", None),    // K7 Total Security
-                ("K7TSMngr.exe", None),    // K7 TotalSecurity Service Manager
-            ])
-        );
-        self.m.insert(MenuId::ANTIVIRUS_MCAFEE, MenuEntry::new(
-            "McAfee",
-            vec![
-                ("mcapexe.exe", None),  // McAfee Access Protection
-                ("mcshield.exe", None), // Part of McAfee real-time protection
-                ("McUICnt.exe", None),  // McAfee HTML User Interface (UI) Container
-                ("MfeAVSvc.exe", None), // McAfee Cloud AV
-                ("mfemms.exe", None),   // McAfee Management Service
-                ("mfevtps.exe", None),  // McAfee Process Validation Service
-                ("MMSSHOST.exe", None), // McAfee Management Service Host
-                ("QcShm.exe", None),    // McAfee QuickClean
-                ("cpd.exe", None),      // McAfee firewall
-                ("PEFService.exe", None),          // Intel Security PEF Service
-                ("ModuleCoreService.exe", None),   // McAfee Module Core Service
-                ("ProtectedModuleHost.exe", None), // McAfee Protected Module Host
-            ])
-        );
-        self.m.insert(MenuId::FIREWALL_COMODO, MenuEntry::new(
-            "Comodo",
-            vec![
-                ("cmdagent.exe", None), // COMODO Internet Security Agent
-                ("cavwp.exe", None),    // COMODO Anti-virus Windows Process
-                ("vkise.exe", None),    // COMODO Internet Security Essentials
-                ("cis.exe", None),      // COMODO Internet Security
-                ("cmdvirth.exe", None), // COMODO Virtual Service Manager
-                ("CPF.exe", None),      // COMODO Personal firewall
-                ("cpf9x206.exe", None),
-                ("cpfnt206.exe", None),
-            ])
-        );
-        self.m.insert(MenuId::FIREWALL_GLASSWIRE, MenuEntry::new(
-            "GlassWire",
-            vec![
-                ("GlassWire.exe", None), // GlassWire firewall
-                ("GWCtlSrv.exe", None),  // GlassWire Control Service
-                ("GWIdlMon.exe", None),  // GlassWire Computer Idle Monitor
-            ])
-        );
-        self.m.insert(MenuId::FIREWALL_TINYWALL, MenuEntry::new(
-            "TinyWall",
-            vec![("TinyWall.exe", None)]
-        ));
-        self.m.insert(MenuId::FIREWALL_ZONEALARM, MenuEntry::new(
-            "ZoneAlarm",
-            vec![
-                ("ZAAR.exe", None),       //  ZoneAlarm Anti-Ransomware
-                ("IswSvc.exe", None),     // ZoneAlarm Browser Security
-                ("ForceField.exe", None), // ZoneAlarm Browser Security
-                ("zatray.exe", None),     // ZoneAlarm System Tray
-                ("Upgrade.exe", None),    // ZoneAlarm Windows upgrader
-                ("zlclient.exe", None),   // ZoneAlarm Client
-                ("zatutor.exe", None),    // ZoneAlarm Pro Tutor
-                ("zapro.exe", None),      // ZoneAlarm Pro
-                ("EFRService.exe", None), // Check Point Endpoint Forensic Recorder service
-                ("AkSA.exe", None),       // ZoneAlarm AntiKeylogger
-                ("zonealarm.exe", None),  // ZoneAlarm Stub Program for ZAPro
-                // ("zonalm2601.exe", None), // Ancient ZoneAlarm name
-                ("ZaPrivacyService.exe", None),  // ZoneAlarm Firewall
-            ])
-        );
-        self.m.insert(MenuId::TOOLS_PEID, MenuEntry::new(
-            "PEiD",
-            vec![("PEiD.exe", None)]
-        ));
-        self.m.insert(MenuId::TOOLS_RESOURCE_HACKER, MenuEntry::new(
-            "Resource hacker",
-            vec![("ResourceHacker.exe", None)]
-        ));
-        self.m.insert(MenuId::TOOLS_DIE, MenuEntry::new(
-            "Detect It Easy",
-            vec![
-                ("die.exe", None),
-                ("diec.exe", None),
-                ("diel.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_DEBUG_VIEW, MenuEntry::new(
-            "Debug View",
-            vec![
-                ("Dbgview.exe", None),
-                ("dbgview64.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_PROCESS_MONITOR, MenuEntry::new(
-            "Process Monitor",
-            vec![
-                ("Procmon.exe", None),
-                ("Procmon64.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_PROCESS_EXPLORER, MenuEntry::new(
-            "Process Explorer",
-            vec![
-                ("procexp.exe", None),
-                ("procexp64.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_TCPVIEW, MenuEntry::new(
-            "TCP View",
-            vec![
-                ("tcpvcon.exe", None),
-                ("tcpvcon64.exe", None),
-                ("tcpview.exe", None),
-                ("tcpview64.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_WIRESHARK, MenuEntry::new(
-            "Wireshark",
-            vec![
-                ("dumpcap.exe", None),
-                ("Wireshark.exe", None)
-            ]
-        ));
-        self.m.insert(MenuId::TOOLS_PE_TOOLS, MenuEntry::new(
-            "PE Tools",
-            vec![("PETools.exe", None)]
-        ));
-        self.m.insert(MenuId::TOOLS_SPYXX, MenuEntry::new(
-            "Spy++",
-            vec![("spyxx.exe", None)]
-        ));
-        self.m.insert(MenuId::TOOLS_CTK_RES_EDIT, MenuEntry::new(
-            "CTK Res Edit",
-            vec![("CTKResEdit.exe", None)]
-        ));
-        self.m.insert(MenuId::TOOLS_XN_RES_EDITOR, MenuEntry::new(
-            "XN Resource Editor",
-            vec![("XNResourceEditor.exe", None)]
-        ));
+    pub fn init_menu_entries(&mut self, catalog: &Catalog, settings: &Settings) -> std::io::Result<()> {
+        self.launches = LaunchQueue::new(Duration::from_millis(settings.launch_jitter_ms), settings.max_concurrent_launches);
+        for category in &catalog.categories {
+            let category_id = self.allocate_id()?;
+            let mut entry_ids: Vec<MenuId> = Vec::with_capacity(category.entries.len());
+            for entry in &category.entries {
+                if self.keys.contains_key(&entry.id) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Duplicate catalog entry id {0}.", entry.id)
+                    ));
+                }
+                let id = self.allocate_id()?;
+                let process_list = entry.processes.iter().map(|p| (p.clone(), None)).collect();
+                self.m.insert(id, MenuEntry::new(entry.name.clone(), process_list, settings));
+                if let Some(schedule) = &entry.schedule {
+                    self.entry_schedules.insert(id, schedule.clone());
+                }
+                self.keys.insert(entry.id.clone(), id);
+                entry_ids.push(id);
+            }
+            self.categories.push(MenuCategory { id: category_id, name: category.name.clone(), entries: entry_ids });
+        }
+        for profile in &catalog.profiles {
+            let mut entry_ids: Vec<MenuId> = Vec::with_capacity(profile.entries.len());
+            for key in &profile.entries {
+                let id = self.find(key).ok_or_else(|| std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Profile {0} refers to unknown entry {1}.", profile.id, key)
+                ))?;
+                entry_ids.push(id);
+            }
+            let profile_id = self.allocate_id()?;
+            self.profiles.push(MenuProfile {
+                id: profile_id,
+                key: profile.id.clone(),
+                name: profile.name.clone(),
+                entries: entry_ids,
+                rotation: profile.rotation,
+                schedule: profile.schedule.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the menu with the one built from a new catalog and settings.
+    /// Running entries that didn't change keep running, removed ones are stopped,
+    /// changed ones are restarted. On error while building, the current state is left as is.
+    /// A changed entry that can't be stopped keeps running as it was, a removed one is stopped
+    /// by a later reconcile; either way the error is returned.
+    pub fn reload(&mut self, catalog: &Catalog, settings: &Settings) -> std::io::Result<()> {
+        let mut fresh = MenuState::new();
+        fresh.init_menu_entries(catalog, settings)?;
+
+        let paused_keys: Vec<String> = self.keys.iter()
+            .filter(|(_, id)| self.paused_process_list.contains(id))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let active_profile_key = self.get_active_profile_key();
+        let old_keys: BTreeMap<MenuId, String> = self.keys.iter().map(|(key, id)| (*id, key.clone())).collect();
+
+        // Entries to stop, changed ones with the id of their replacement, removed ones without
+        let mut stopping: Vec<(MenuEntry, Option<MenuId>)> = Vec::new();
+        let keys = std::mem::take(&mut self.keys);
+        for (key, old_id) in keys {
+            let Some(mut old) = self.m.remove(&old_id) else { continue };
+            let was_active = old.is_process_active();
+            match fresh.find(&key) {
+                Some(id) => {
+                    let new = fresh.m.get_mut(&id).expect("Entry was just found by key.");
+                    if !new.take_over(&mut old) && was_active {
+                        #[cfg(feature = "logger")] debug!("Restarting changed entry {0}.", key);
+                        stopping.push((old, Some(id)));
+                    }
+                }
+                None if was_active => {
+                    #[cfg(feature = "logger")] debug!("Stopping removed entry {0}.", key);
+                    stopping.push((old, None));
+                }
+                None => {}
+            }
+        }
+        // Like disable_all, all stubs are asked first and share one deadline
+        for (old, _) in &mut stopping {
+            old.request_stop();
+        }
+        let requested = Instant::now();
+        let mut res: std::io::Result<()> = Ok(());
+        for (mut old, id) in stopping {
+            match (old.finish_stop(requested), id) {
+                (Ok(()), Some(id)) => res = res.and(fresh.m.get_mut(&id).expect("Entry was just found by key.").start_process()),
+                (Ok(()), None) => {}
+                // Its stubs still run, the next reload tries the change again
+                (Err(e), Some(id)) => {
+                    res = res.and(Err(e));
+                    fresh.m.insert(id, old);
+                }
+                (Err(e), None) => {
+                    res = res.and(Err(e));
+                    fresh.retired.push(old);
+                }
+            }
+        }
+
+        // Queued entries keep their turn, schedule-controlled ones their last known window
+        let fresh_keys = &fresh.keys;
+        let map = |id: &MenuId| old_keys.get(id).and_then(|key| fresh_keys.get(key).copied());
+        fresh.launches.take_over(&mut self.launches, map);
+        fresh.scheduler.take_over(&mut self.scheduler, map);
+        fresh.rng = self.rng.clone();
+        fresh.retired.append(&mut self.retired);
+        fresh.is_paused = self.is_paused;
+        fresh.paused_process_list = paused_keys.iter().filter_map(|key| fresh.find(key)).collect();
+        fresh.active_profile = active_profile_key.and_then(|key| fresh.find_profile(&key));
+        // The rotating profile keeps rotating on schedule, with the new pool
+        if let Some(old) = &self.rotation {
+            fresh.rotation = fresh.new_rotation();
+            if let Some(rotation) = &mut fresh.rotation {
+                rotation.keep_schedule(old);
+            }
+        }
+        *self = fresh;
+        res
+    }
+
+    fn allocate_id(&mut self) -> std::io::Result<MenuId> {
+        self.id_registry.allocate().ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::OutOfMemory,
+            "Too many catalog entries, no free menu ids left."
+        ))
     }
 
 }
\ No newline at end of file
@@ -35,7 +35,7 @@ impl MenuTray {
         } else {
             MF_UNCHECKED.0
         };
-        CheckMenuItem(self.menu, MenuId::AUTOSTART as u32, autostart_tick);
+        CheckMenuItem(self.menu, MenuId::AUTOSTART.0, autostart_tick);
     }
 
     unsafe fn append_last_entries(&mut self, autostart: bool) {
@@ -49,19 +49,19 @@ impl MenuTray {
         AppendMenuW(
             self.menu,
             MF_STRING | autostart_tick,
-            MenuId::AUTOSTART as usize,
+            MenuId::AUTOSTART.0 as usize,
             w!("Autostart"),
         );
         AppendMenuW(
             self.menu,
             MF_STRING,
-            MenuId::ABOUT as usize,
+            MenuId::ABOUT.0 as usize,
             w!("About"),
         );
         AppendMenuW(
             self.menu,
             MF_STRING,
-            MenuId::EXIT as usize,
+            MenuId::EXIT.0 as usize,
             w!("Exit"),
         );
     }
@@ -74,7 +74,7 @@ impl MenuTray {
         AppendMenuW(
             self.menu,
             MF_STRING,
-            MenuId::RESUME as usize,
+            MenuId::RESUME.0 as usize,
             w!("Resume"),
         );
         self.append_last_entries(autostart);
@@ -83,114 +83,47 @@ impl MenuTray {
 
     pub unsafe fn create_menu_active(&mut self, menu_state: &MenuState, autostart: bool) -> windows::core::Result<()> {
         assert!(!self.is_initialized());
-        let guest_entries: &[MenuId] = &[
-            MenuId::GUEST_VIRTUALBOX,
-            MenuId::GUEST_VMWARE,
-            MenuId::GUEST_PARALLELS,
-            MenuId::GUEST_HYPERV,
-            MenuId::GUEST_VIRTUAL_PC,
-        ];
-
-        let debugger_entries: &[MenuId] = &[
-            MenuId::DEBUGGER_OLLY,
-            MenuId::DEBUGGER_WINDBG,
-            MenuId::DEBUGGER_X64DBG,
-            MenuId::DEBUGGER_IDA,
-            MenuId::DEBUGGER_IMMUNITY,
-            MenuId::DEBUGGER_RADARE2,
-            MenuId::DEBUGGER_BINARY_NINJA,
-        ];
-
-        let antivirus_entries: &[MenuId] = &[
-            MenuId::ANTIVIRUS_AVIRA,
-            MenuId::ANTIVIRUS_ESCAN,
-            MenuId::ANTIVIRUS_FORTINET,
-            MenuId::ANTIVIRUS_GDATA,
-            MenuId::ANTIVIRUS_K7,
-            MenuId::ANTIVIRUS_MCAFEE,
-        ];
-
-        let firewall_entries: &[MenuId] = &[
-                MenuId::FIREWALL_COMODO,
-                MenuId::FIREWALL_GLASSWIRE,
-                MenuId::FIREWALL_TINYWALL,
-                MenuId::FIREWALL_ZONEALARM,
-        ];
-
-        let tools_entries: &[MenuId] = &[
-            MenuId::TOOLS_PEID,
-            MenuId::TOOLS_RESOURCE_HACKER,
-            MenuId::TOOLS_DIE,
-            MenuId::TOOLS_DEBUG_VIEW,
-            MenuId::TOOLS_PROCESS_MONITOR,
-            MenuId::TOOLS_PROCESS_EXPLORER,
-            MenuId::TOOLS_TCPVIEW,
-            MenuId::TOOLS_WIRESHARK,
-            MenuId::TOOLS_PE_TOOLS,
-            MenuId::TOOLS_SPYXX,
-            MenuId::TOOLS_CTK_RES_EDIT,
-            MenuId::TOOLS_XN_RES_EDITOR,
-        ];
-
-        let guest_submenu: HMENU = CreatePopupMenu()?;
-        append_menu(guest_submenu, menu_state, guest_entries);
-
-        let debugger_submenu: HMENU = CreatePopupMenu()?;
-        append_menu(debugger_submenu, menu_state, debugger_entries);
-
-        let antivirus_submenu: HMENU = CreatePopupMenu()?;
-        append_menu(antivirus_submenu, menu_state, antivirus_entries);
-
-        let firewall_submenu: HMENU = CreatePopupMenu()?;
-        append_menu(firewall_submenu, menu_state, firewall_entries);
-
-        let tools_submenu: HMENU = CreatePopupMenu()?;
-        append_menu(tools_submenu, menu_state, tools_entries);
+
+        let mut submenus: Vec<(HMENU, &str)> = Vec::new();
+        for category in menu_state.get_categories() {
+            let submenu: HMENU = CreatePopupMenu()?;
+            append_menu(submenu, menu_state, &category.entries);
+            submenus.push((submenu, &category.name));
+        }
 
         self.menu = CreatePopupMenu()?;
         AppendMenuW(
             self.menu,
             MF_STRING,
-            MenuId::PAUSE as usize,
+            MenuId::PAUSE.0 as usize,
             w!("Pause"),
         );
+        if !menu_state.get_profiles().is_empty() {
+            let profile_submenu: HMENU = CreatePopupMenu()?;
+            append_profiles(profile_submenu, menu_state);
+            AppendMenuW(
+                self.menu,
+                MF_STRING | MF_POPUP,
+                profile_submenu.0 as usize,
+                w!("Profile"),
+            );
+        }
         AppendMenuW(self.menu, MF_SEPARATOR, 0, None);
-        AppendMenuW(
-            self.menu,
-            MF_STRING | MF_POPUP,
-            guest_submenu.0 as usize,
-            w!("VM guest process"),
-        );
-        AppendMenuW(
-            self.menu,
-            MF_STRING | MF_POPUP,
-            debugger_submenu.0 as usize,
-            w!("Debugger"),
-        );
-        AppendMenuW(
-            self.menu,
-            MF_STRING | MF_POPUP,
-            antivirus_submenu.0 as usize,
-            w!("Antivirus"),
-        );
-        AppendMenuW(
-            self.menu,
-            MF_STRING | MF_POPUP,
-            firewall_submenu.0 as usize,
-            w!("Firewall"),
-        );
-        AppendMenuW(
-            self.menu,
-            MF_STRING | MF_POPUP,
-            tools_submenu.0 as usize,
-            w!("Tools"),
-        );
+        for (submenu, name) in submenus {
+            AppendMenuW(
+                self.menu,
+                MF_STRING | MF_POPUP,
+                submenu.0 as usize,
+                to_pcwstr(name).1,
+            );
+        }
         self.append_last_entries(autostart);
         Ok(())
     }
 
     pub unsafe fn destroy(&mut self) {
         DestroyMenu(self.menu);
+        self.menu = HMENU(-1);
     }
 }
 
@@ -201,13 +134,46 @@ fn append_menu(menu: HMENU, menu_state: &MenuState, entry_ids: &[MenuId]) {
         } else {
             MF_UNCHECKED
         };
+        let mut marks: Vec<&str> = Vec::new();
+        // Switched on and off by a schedule
+        if menu_state.is_scheduled(e) {
+            marks.push("scheduled");
+        }
+        // Enabled, but the reconciler can't keep its stubs running
+        if menu_state.is_degraded(e) {
+            marks.push("degraded");
+        }
+        let name: String = if marks.is_empty() {
+            menu_state.get_name(e).to_string()
+        } else {
+            format!("{0} ({1})", menu_state.get_name(e), marks.join(", "))
+        };
         unsafe {
             AppendMenuW(
                 menu,
                 bird | MF_STRING,
-                *e as usize,
-                to_pcwstr(menu_state.get_name(e)).1,
+                e.0 as usize,
+                to_pcwstr(&name).1,
             )
         };
     }
-}
\ No newline at end of file
+}
+
+fn append_profiles(menu: HMENU, menu_state: &MenuState) {
+    let active_profile = menu_state.get_active_profile();
+    for p in menu_state.get_profiles() {
+        let bird = if active_profile == Some(p.id) {
+            MF_CHECKED
+        } else {
+            MF_UNCHECKED
+        };
+        unsafe {
+            AppendMenuW(
+                menu,
+                bird | MF_STRING,
+                p.id.0 as usize,
+                to_pcwstr(&p.name).1,
+            )
+        };
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+#[cfg(windows)]
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::Foundation::*,
+    Win32::Security::{GetTokenInformation, TokenUser, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY, TOKEN_USER},
+    Win32::Security::Authorization::{ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+    Win32::Storage::FileSystem::{FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+    Win32::System::Memory::LocalFree,
+    Win32::System::Pipes::*,
+    Win32::System::Threading::{GetCurrentProcess, OpenProcessToken},
+};
+
+use crate::status::{Message, StubEvent, HEARTBEAT_TIMEOUT};
+
+// Events nobody picked up are dropped beyond this, a stub flooded with messages mustn't eat memory
+const MAX_EVENTS: usize = 100;
+
+// What the resident knows about a stub from its status channel
+#[derive(Default)]
+struct StubStatus {
+    ready: bool,
+    connected: bool,
+    last_seen: Option<Instant>,
+    events: Vec<StubEvent>,
+}
+
+type StatusMap = Arc<Mutex<BTreeMap<u32, StubStatus>>>;
+
+// Listens on the status channel and keeps the last known status of every registered stub
+pub struct StubMonitor {
+    name: String,
+    stubs: StatusMap,
+    next_id: AtomicU32,
+}
+
+impl StubMonitor {
+    /// Opens the channel `name` and accepts stubs that introduce themselves with `token`.
+    pub fn start(name: &str, token: &str) -> io::Result<StubMonitor> {
+        let stubs: StatusMap = Arc::default();
+        listen(name, token.to_string(), stubs.clone())?;
+        Ok(StubMonitor { name: name.to_string(), stubs, next_id: AtomicU32::new(1) })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Reserves an id for a stub about to be started. Connections with unknown ids are dropped.
+    pub fn register(&self) -> u32 {
+        let stub_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.lock().insert(stub_id, StubStatus::default());
+        stub_id
+    }
+
+    pub fn forget(&self, stub_id: u32) {
+        self.lock().remove(&stub_id);
+    }
+
+    /// True if the stub is ready, still connected and was heard from shortly before `now`.
+    #[must_use]
+    pub fn is_alive(&self, stub_id: u32, now: Instant) -> bool {
+        self.lock().get(&stub_id).is_some_and(|s| {
+            s.ready && s.connected && s.last_seen.is_some_and(|t| now.saturating_duration_since(t) < HEARTBEAT_TIMEOUT)
+        })
+    }
+
+    /// True if the stub, started at `started`, had time to report and went silent at `now`.
+    /// A stub that never connected says nothing about its state, e.g. the channel failed on its side,
+    /// so only its process tells whether it runs.
+    #[must_use]
+    pub fn is_hung(&self, stub_id: u32, started: Instant, now: Instant) -> bool {
+        let connected_once = self.lock().get(&stub_id).is_some_and(|s| s.last_seen.is_some());
+        connected_once && now.saturating_duration_since(started) > HEARTBEAT_TIMEOUT && !self.is_alive(stub_id, now)
+    }
+
+    /// Events the stub reported since the last call.
+    pub fn take_events(&self, stub_id: u32) -> Vec<StubEvent> {
+        self.lock().get_mut(&stub_id).map(|s| std::mem::take(&mut s.events)).unwrap_or_default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u32, StubStatus>> {
+        lock(&self.stubs)
+    }
+}
+
+fn lock(stubs: &StatusMap) -> MutexGuard<'_, BTreeMap<u32, StubStatus>> {
+    // A panicked connection thread doesn't make the statuses of other stubs wrong
+    stubs.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Welcomes a stub of this session, then reads its messages until it disconnects.
+// `answers` is the sending half of the same connection.
+fn serve<R: Read, W: Write>(connection: R, mut answers: W, token: &str, stubs: &StatusMap) {
+    let mut lines = BufReader::new(connection).lines();
+    let stub_id = match lines.next().and_then(|l| l.ok()).and_then(|l| Message::parse(&l)) {
+        Some(Message::Hello { token: hello_token, stub_id }) if hello_token == token => stub_id,
+        // Not one of our stubs
+        _ => return,
+    };
+    match lock(stubs).get_mut(&stub_id) {
+        Some(status) => {
+            status.connected = true;
+            status.last_seen = Some(Instant::now());
+        }
+        None => return,
+    }
+    if Message::Welcome.write(&mut answers).is_err() {
+        if let Some(status) = lock(stubs).get_mut(&stub_id) {
+            status.connected = false;
+        }
+        return;
+    }
+    for line in lines {
+        let Ok(line) = line else { break };
+        let mut stubs = lock(stubs);
+        // Forgotten meanwhile, the stub was stopped
+        let Some(status) = stubs.get_mut(&stub_id) else { return };
+        status.last_seen = Some(Instant::now());
+        match Message::parse(&line) {
+            Some(Message::Ready) => status.ready = true,
+            Some(Message::Event(event)) if status.events.len() < MAX_EVENTS => status.events.push(event),
+            // Heartbeats and anything else only prove the stub is alive
+            _ => {}
+        }
+    }
+    if let Some(status) = lock(stubs).get_mut(&stub_id) {
+        status.connected = false;
+    }
+}
+
+#[cfg(unix)]
+fn listen(name: &str, token: String, stubs: StatusMap) -> io::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    // Left over by a session that crashed
+    let _ = std::fs::remove_file(name);
+    let listener = UnixListener::bind(name)?;
+    std::thread::spawn(move || {
+        for connection in listener.incoming().flatten() {
+            let Ok(answers) = connection.try_clone() else { continue };
+            let (token, stubs) = (token.clone(), stubs.clone());
+            std::thread::spawn(move || serve(connection, answers, &token, &stubs));
+        }
+    });
+    Ok(())
+}
+
+// One pipe instance per stub, a new one is created as soon as a stub connects
+#[cfg(windows)]
+fn listen(name: &str, token: String, stubs: StatusMap) -> io::Result<()> {
+    use std::fs::File;
+    use std::os::windows::io::FromRawHandle;
+
+    let name = crate::convert::to_utf16(name);
+    let security = OwnerOnly::new()?;
+    // Fails if another process already owns the name
+    let mut pipe = create_pipe_instance(&name, FILE_FLAG_FIRST_PIPE_INSTANCE, &security)?;
+    std::thread::spawn(move || loop {
+        // The stub may connect between creating the instance and waiting for it
+        let connected = unsafe { ConnectNamedPipe(pipe, None) }.as_bool()
+            || io::Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED.0 as i32);
+        if connected {
+            // The files close the pipe instance when the stub disconnects
+            let connection = unsafe { File::from_raw_handle(pipe.0 as _) };
+            if let Ok(answers) = connection.try_clone() {
+                let (token, stubs) = (token.clone(), stubs.clone());
+                std::thread::spawn(move || serve(connection, answers, &token, &stubs));
+            }
+        } else {
+            unsafe { CloseHandle(pipe) };
+        }
+        pipe = match create_pipe_instance(&name, FILE_FLAGS_AND_ATTRIBUTES(0), &security) {
+            Ok(pipe) => pipe,
+            Err(_e) => {
+                #[cfg(feature = "logger")] error!("Status channel closed: {0}", _e);
+                break;
+            }
+        };
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn create_pipe_instance(name: &[u16], flags: FILE_FLAGS_AND_ATTRIBUTES, security: &OwnerOnly) -> io::Result<HANDLE> {
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security.0 .0,
+        bInheritHandle: false.into(),
+    };
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PCWSTR(name.as_ptr()),
+            PIPE_ACCESS_DUPLEX | flags,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            512,
+            512,
+            0,
+            Some(&attributes),
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pipe)
+}
+
+// Security descriptor that lets only the user running the resident open the status channel
+#[cfg(windows)]
+struct OwnerOnly(PSECURITY_DESCRIPTOR);
+
+// Owned memory that nothing else points to, the listening thread takes it along
+#[cfg(windows)]
+unsafe impl Send for OwnerOnly {}
+
+#[cfg(windows)]
+impl OwnerOnly {
+    fn new() -> io::Result<OwnerOnly> {
+        // Protected DACL with a single entry, nothing is inherited from the pipe namespace
+        let sddl = crate::convert::to_utf16(&format!("D:P(A;;GA;;;{0})", current_user_sid()?));
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(PCWSTR(sddl.as_ptr()), SDDL_REVISION_1, &mut descriptor, None)
+        }.ok()?;
+        Ok(OwnerOnly(descriptor))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for OwnerOnly {
+    fn drop(&mut self) {
+        unsafe { LocalFree(self.0 .0 as isize) };
+    }
+}
+
+#[cfg(windows)]
+fn current_user_sid() -> io::Result<String> {
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) }.ok()?;
+    // TOKEN_USER followed by the SID it points to, usize keeps it aligned
+    let mut buffer = [0usize; 64];
+    let mut size: u32 = 0;
+    let res = unsafe {
+        GetTokenInformation(token, TokenUser, Some(buffer.as_mut_ptr().cast()), std::mem::size_of_val(&buffer) as u32, &mut size)
+    }.ok();
+    unsafe { CloseHandle(token) };
+    res?;
+    let user = unsafe { &*buffer.as_ptr().cast::<TOKEN_USER>() };
+    let mut text = PWSTR::null();
+    unsafe { ConvertSidToStringSidW(user.User.Sid, &mut text) }.ok()?;
+    let sid = unsafe { text.to_string() };
+    unsafe { LocalFree(text.0 as isize) };
+    sid.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::status::{channel_name, read_welcome};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    const TOKEN: &str = "5E55105E55105E55105E55105E55105E55105E55105E55105E55105E55105E55";
+    const NONCE: &str = "00112233445566778899AABBCCDDEEFF";
+
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        for _ in 0..200 {
+            if condition() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn tracks_registered_stubs() {
+        let monitor = StubMonitor::start(&channel_name(NONCE), TOKEN).unwrap();
+        let stub_id = monitor.register();
+        let stranger_id = monitor.register();
+        assert!(!monitor.is_alive(stub_id, Instant::now()));
+
+        let mut stranger = UnixStream::connect(monitor.name()).unwrap();
+        write!(stranger, "HELLO {0} {1}\nREADY\n", TOKEN.replace('5', "6"), stranger_id).unwrap();
+        assert!(read_welcome(&mut BufReader::new(&stranger)).is_err());
+
+        let mut stub = UnixStream::connect(monitor.name()).unwrap();
+        writeln!(stub, "HELLO {0} {1}", TOKEN, stub_id).unwrap();
+        read_welcome(&mut BufReader::new(&stub)).unwrap();
+        // Ready comes last, so the event is there once the stub is alive
+        write!(stub, "EVENT close\nHEARTBEAT\nREADY\n").unwrap();
+        assert!(wait_until(|| monitor.is_alive(stub_id, Instant::now())));
+        assert_eq!(monitor.take_events(stub_id), vec![StubEvent::CloseRequested]);
+        assert!(monitor.take_events(stub_id).is_empty());
+        assert!(!monitor.is_alive(stranger_id, Instant::now()));
+
+        drop(stub);
+        assert!(wait_until(|| !monitor.is_alive(stub_id, Instant::now())));
+        monitor.forget(stub_id);
+        assert!(monitor.take_events(stub_id).is_empty());
+    }
+
+    #[test]
+    fn silent_stub_is_hung_only_after_connecting() {
+        let monitor = StubMonitor::start(&channel_name("FFEEDDCCBBAA99887766554433221100"), TOKEN).unwrap();
+        let started = Instant::now();
+        let later = started + HEARTBEAT_TIMEOUT * 2;
+        // Never connected, its process is all there is to judge by
+        let stub_id = monitor.register();
+        assert!(!monitor.is_hung(stub_id, started, later));
+
+        let mut stub = UnixStream::connect(monitor.name()).unwrap();
+        write!(stub, "HELLO {0} {1}\nREADY\n", TOKEN, stub_id).unwrap();
+        assert!(wait_until(|| monitor.is_alive(stub_id, Instant::now())));
+        assert!(!monitor.is_hung(stub_id, started, Instant::now()));
+        assert!(monitor.is_hung(stub_id, started, Instant::now() + HEARTBEAT_TIMEOUT));
+        assert!(!monitor.is_hung(monitor.register(), started, later));
+    }
+}
@@ -0,0 +1,264 @@
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::{fs, io, path::{Path, PathBuf}};
+
+use crate::catalog::{Catalog, Category};
+use crate::validate::{validate, Issue};
+
+const PACK_VERSION: u32 = 1;
+#[cfg_attr(not(windows), allow(dead_code))]
+const PACK_EXTENSION: &str = "toml";
+// Detached signature of the pack file, e.g. "evasion.toml.sig"
+#[cfg_attr(not(windows), allow(dead_code))]
+const SIGNATURE_SUFFIX: &str = ".sig";
+
+// Signature pack: extra categories and entries distributed separately from the resident
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pack {
+    pub version: u32,
+    pub id: String,
+    #[serde(rename = "category", default)]
+    pub categories: Vec<Category>,
+}
+
+impl Pack {
+    /// Reads the pack at `path` and checks its signature against the trusted `keys`.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn load(path: &Path, keys: &[VerifyingKey]) -> io::Result<Pack> {
+        let data = fs::read(path)?;
+        let signature = fs::read_to_string(signature_path(path))?;
+        verify(&data, &signature, keys)?;
+        let text = String::from_utf8(data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Pack::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<Pack> {
+        let pack: Pack = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if pack.version != PACK_VERSION {
+            let msg = format!("Unsupported pack version {0}, expected {1}.", pack.version, PACK_VERSION);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        // A pack must be valid on its own, conflicts with the catalog are checked on merge
+        let catalog = Catalog { version: pack.version, categories: pack.categories, profiles: Vec::new() };
+        let issues = validate(&catalog);
+        if !issues.is_empty() {
+            let msg = issues.iter().map(|i| i.to_string()).collect::<Vec<String>>().join("\n");
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        Ok(Pack { version: pack.version, id: pack.id, categories: catalog.categories })
+    }
+
+    /// Adds pack entries to the catalog. Categories with the same id are extended.
+    /// Entries whose id or process names are already in the catalog are skipped and reported.
+    pub fn merge_into(self, catalog: &mut Catalog) -> Vec<Issue> {
+        let mut issues: Vec<Issue> = Vec::new();
+        let mut entry_ids: Vec<String> = Vec::new();
+        // Lowercase process name => entry id
+        let mut process_owners: BTreeMap<String, String> = BTreeMap::new();
+        for entry in catalog.categories.iter().flat_map(|c| &c.entries) {
+            entry_ids.push(entry.id.clone());
+            for process in &entry.processes {
+                process_owners.insert(process.name.to_lowercase(), entry.id.clone());
+            }
+        }
+
+        for mut category in self.categories {
+            let entries = std::mem::take(&mut category.entries);
+            let mut accepted = Vec::with_capacity(entries.len());
+            for entry in entries {
+                if entry_ids.contains(&entry.id) {
+                    issues.push(Issue { owner: entry.id, message: format!("pack {0}: entry id already exists", self.id) });
+                    continue;
+                }
+                let collision = entry.processes.iter()
+                    .find_map(|p| process_owners.get(&p.name.to_lowercase()).map(|owner| (&p.name, owner)));
+                if let Some((process, owner)) = collision {
+                    let message = format!("pack {0}: process \"{1}\" is already used by {2}", self.id, process, owner);
+                    issues.push(Issue { owner: entry.id, message });
+                    continue;
+                }
+                accepted.push(entry);
+            }
+
+            match catalog.categories.iter_mut().find(|c| c.id == category.id) {
+                Some(existing) => existing.entries.extend(accepted),
+                None if !accepted.is_empty() => {
+                    category.entries = accepted;
+                    catalog.categories.push(category);
+                }
+                None => {}
+            }
+        }
+        issues
+    }
+}
+
+/// Pack files in `folder`, sorted by name. A missing folder means no packs.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn find_packs(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = match fs::read_dir(folder) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut packs: Vec<PathBuf> = Vec::new();
+    for item in dir {
+        let path = item?.path();
+        if path.is_file() && path.extension().is_some_and(|e| e.eq_ignore_ascii_case(PACK_EXTENSION)) {
+            packs.push(path);
+        }
+    }
+    packs.sort();
+    Ok(packs)
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn signature_path(pack: &Path) -> PathBuf {
+    let mut path = pack.as_os_str().to_owned();
+    path.push(SIGNATURE_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Loads all packs from `folder` into the catalog. Returns problems of rejected packs and entries,
+/// a broken pack doesn't prevent the others from loading.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn merge_packs(catalog: &mut Catalog, folder: &Path, trusted_keys: &[String]) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+    let packs = match find_packs(folder) {
+        Ok(packs) => packs,
+        Err(e) => return vec![format!("{0}: {1}", folder.to_string_lossy(), e)],
+    };
+    if packs.is_empty() {
+        return problems;
+    }
+    let keys: Vec<VerifyingKey> = trusted_keys.iter().filter_map(|k| parse_key(k).ok()).collect();
+    if keys.is_empty() {
+        problems.push("signature packs are ignored, no trusted keys in the settings".to_string());
+        return problems;
+    }
+    for path in packs {
+        match Pack::load(&path, &keys) {
+            Ok(pack) => problems.extend(pack.merge_into(catalog).iter().map(|i| i.to_string())),
+            Err(e) => problems.push(format!("{0}: {1}", path.to_string_lossy(), e)),
+        }
+    }
+    problems
+}
+
+/// Parses a hex encoded Ed25519 public key.
+pub fn parse_key(text: &str) -> io::Result<VerifyingKey> {
+    let bytes: [u8; 32] = decode_hex(text)?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Not an Ed25519 public key."))
+}
+
+fn verify(data: &[u8], signature: &str, keys: &[VerifyingKey]) -> io::Result<()> {
+    let signature = Signature::from_bytes(&decode_hex(signature)?);
+    if keys.iter().any(|k| k.verify_strict(data, &signature).is_ok()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "Signature doesn't match any trusted key."))
+    }
+}
+
+fn decode_hex<const N: usize>(text: &str) -> io::Result<[u8; N]> {
+    let text = text.trim();
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Expected {0} hex digits.", N * 2));
+    if text.len() != N * 2 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const PACK: &str = r#"
+        version = 1
+        id = "RESEARCH"
+        [[category]]
+        id = "TOOLS"
+        name = "Tools"
+        [[category.entry]]
+        id = "TOOLS_NEW"
+        name = "New tool"
+        processes = ["newtool.exe"]
+        [[category.entry]]
+        id = "TOOLS_PEID"
+        name = "PEiD again"
+        processes = ["other.exe"]
+        [[category.entry]]
+        id = "TOOLS_COPY"
+        name = "Copy"
+        processes = ["PEID.EXE"]
+        [[category]]
+        id = "RESEARCH"
+        name = "Research"
+        [[category.entry]]
+        id = "RESEARCH_A"
+        name = "A"
+        processes = ["a.exe"]
+    "#;
+
+    fn catalog() -> Catalog {
+        Catalog::parse(r#"
+            version = 1
+            [[category]]
+            id = "TOOLS"
+            name = "Tools"
+            [[category.entry]]
+            id = "TOOLS_PEID"
+            name = "PEiD"
+            processes = ["peid.exe"]
+        "#).unwrap()
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|v| format!("{:02X}", v)).collect()
+    }
+
+    #[test]
+    fn conflicts_are_reported_not_overwritten() {
+        let mut catalog = catalog();
+        let issues = Pack::parse(PACK).unwrap().merge_into(&mut catalog);
+        let owners: Vec<&str> = issues.iter().map(|i| i.owner.as_str()).collect();
+        assert_eq!(owners, vec!["TOOLS_PEID", "TOOLS_COPY"]);
+
+        let tools: Vec<&str> = catalog.categories[0].entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(tools, vec!["PEiD", "New tool"]);
+        assert_eq!(catalog.categories[1].id, "RESEARCH");
+        assert!(validate(&catalog).is_empty());
+    }
+
+    #[test]
+    fn signature_is_checked_against_trusted_keys() {
+        let signer = SigningKey::from_bytes(&[7u8; 32]);
+        let stranger = SigningKey::from_bytes(&[8u8; 32]);
+        let trusted = vec![parse_key(&to_hex(signer.verifying_key().as_bytes())).unwrap()];
+        let signature = to_hex(&signer.sign(PACK.as_bytes()).to_bytes());
+
+        assert!(verify(PACK.as_bytes(), &signature, &trusted).is_ok());
+        let tampered = PACK.replace("a.exe", "b.exe");
+        assert!(verify(tampered.as_bytes(), &signature, &trusted).is_err());
+        let foreign = to_hex(&stranger.sign(PACK.as_bytes()).to_bytes());
+        assert_eq!(verify(PACK.as_bytes(), &foreign, &trusted).err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+        assert!(verify(PACK.as_bytes(), "00", &trusted).is_err());
+    }
+
+    #[test]
+    fn invalid_pack_is_rejected() {
+        assert!(Pack::parse("version = 1\nid = \"P\"\n[[category]]\nid = \"C\"\nname = \"C\"\n\
+            [[category.entry]]\nid = \"E\"\nname = \"E\"\nprocesses = [\"CON\"]").is_err());
+        assert!(Pack::parse("version = 2\nid = \"P\"").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// Small pseudo random generator (SplitMix64) for timing decisions like launch jitter.
+// Not for secrets, the session token comes from the system RNG.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Same seed, same sequence, so tests can replay decisions.
+    pub const fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seed that differs between runs; std seeds its hash maps from the system RNG.
+    #[must_use]
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn entropy_seed() -> u64 {
+        RandomState::new().build_hasher().finish()
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..=max`.
+    pub fn up_to(&mut self, max: u64) -> u64 {
+        match max.checked_add(1) {
+            Some(bound) => self.next_u64() % bound,
+            None => self.next_u64(),
+        }
+    }
+
+    /// Uniform in `0..=max`, with millisecond resolution.
+    pub fn duration_up_to(&mut self, max: Duration) -> Duration {
+        let max_ms = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+        Duration::from_millis(self.up_to(max_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_replays_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let first: Vec<u64> = (0..8).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..8).map(|_| b.next_u64()).collect::<Vec<u64>>());
+        assert_ne!(first[0], Rng::new(43).next_u64());
+
+        for _ in 0..1000 {
+            assert!(a.up_to(3) <= 3);
+            assert!(a.duration_up_to(Duration::from_millis(250)) <= Duration::from_millis(250));
+        }
+        assert_eq!(a.duration_up_to(Duration::ZERO), Duration::ZERO);
+    }
+}
@@ -0,0 +1,130 @@
+use std::time::{Duration, Instant};
+
+use crate::catalog::ProfileRotation;
+use crate::menu_ids::MenuId;
+use crate::random::Rng;
+use crate::switch::{BatchResult, Switch};
+
+// "Moving target" mode of a profile: a random part of the pool runs, and every now and then
+// one running entry is swapped for one that doesn't, so the set of decoys never settles.
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    pool: Vec<MenuId>,
+    count: usize,
+    min_interval: Duration,
+    max_interval: Duration,
+    next_change: Option<Instant>,
+}
+
+impl Rotation {
+    pub fn new(pool: Vec<MenuId>, config: &ProfileRotation) -> Rotation {
+        Rotation {
+            pool,
+            count: config.count,
+            min_interval: Duration::from_secs(config.min_interval_s),
+            max_interval: Duration::from_secs(config.max_interval_s.max(config.min_interval_s)),
+            next_change: None,
+        }
+    }
+
+    /// Random `count` entries of the pool to start with. Schedules the first change.
+    pub fn pick(&mut self, rng: &mut Rng, now: Instant) -> Vec<MenuId> {
+        let mut pool = self.pool.clone();
+        // Partial Fisher-Yates shuffle, the first `count` entries are the pick
+        let count = self.count.min(pool.len());
+        for i in 0..count {
+            let j = i + rng.up_to((pool.len() - 1 - i) as u64) as usize;
+            pool.swap(i, j);
+        }
+        pool.truncate(count);
+        self.schedule(rng, now);
+        pool
+    }
+
+    #[must_use]
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_change.is_some_and(|at| now >= at)
+    }
+
+    /// Swaps a random enabled entry of the pool for a random disabled one. If entries failed
+    /// to start or were stopped meanwhile, only enables or disables one to get back to `count`.
+    pub fn rotate<S: Switch>(&mut self, switch: &mut S, rng: &mut Rng, now: Instant) -> BatchResult<S::ErrorType> {
+        let mut result = BatchResult::new();
+        let (on, off): (Vec<MenuId>, Vec<MenuId>) = self.pool.iter().partition(|id| switch.is_enabled(id));
+        let swap = on.len() == self.count && !off.is_empty();
+        if on.len() > self.count || swap {
+            let id = on[rng.up_to(on.len() as u64 - 1) as usize];
+            result.add(id, switch.disable(&id));
+        }
+        if (on.len() < self.count || swap) && !off.is_empty() {
+            let id = off[rng.up_to(off.len() as u64 - 1) as usize];
+            result.add(id, switch.enable(&id));
+        }
+        self.schedule(rng, now);
+        result
+    }
+
+    /// Takes the time of the next change from `other`, e.g. the rotation before a reload.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn keep_schedule(&mut self, other: &Rotation) {
+        self.next_change = other.next_change;
+    }
+
+    fn schedule(&mut self, rng: &mut Rng, now: Instant) {
+        self.next_change = Some(now + self.min_interval + rng.duration_up_to(self.max_interval - self.min_interval));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::switch::tests::Lamps;
+
+    fn rotation() -> Rotation {
+        let pool: Vec<MenuId> = (100..106).map(MenuId).collect();
+        Rotation::new(pool, &ProfileRotation { count: 3, min_interval_s: 60, max_interval_s: 120 })
+    }
+
+    #[test]
+    fn seed_decides_the_pick() {
+        let now = Instant::now();
+        let picks: Vec<Vec<MenuId>> = (0..2).map(|_| rotation().pick(&mut Rng::new(5), now)).collect();
+        assert_eq!(picks[0], picks[1]);
+        assert_eq!(picks[0].len(), 3);
+        assert!(picks[0].iter().all(|id| (100..106).contains(&id.0)));
+        assert!(picks[0].iter().all(|id| picks[0].iter().filter(|i| *i == id).count() == 1));
+
+        // Different seeds end up with different picks sooner or later
+        assert!((0..20).any(|seed| rotation().pick(&mut Rng::new(seed), now) != picks[0]));
+    }
+
+    #[test]
+    fn rotates_one_entry_within_bounds() {
+        let now = Instant::now();
+        let mut rng = Rng::new(11);
+        let mut rotation = rotation();
+        // Entry 105 can't be started
+        let mut lamps = Lamps { on: vec![MenuId(100), MenuId(101), MenuId(102)], broken: |id| id.0 == 105 };
+        assert!(!rotation.is_due(now));
+
+        rotation.pick(&mut rng, now);
+        assert!(!rotation.is_due(now + Duration::from_secs(59)));
+        assert!(rotation.is_due(now + Duration::from_secs(120)));
+
+        let mut now = now;
+        for _ in 0..50 {
+            now += Duration::from_secs(120);
+            let before = lamps.on.clone();
+            let result = rotation.rotate(&mut lamps, &mut rng, now);
+            if result.is_ok() {
+                assert_eq!(lamps.on.len(), 3);
+                assert_eq!(lamps.on.iter().filter(|id| !before.contains(id)).count(), 1);
+            } else {
+                // The broken one leaves a gap, the next rotation only fills it
+                assert_eq!(result.failed[0].0, MenuId(105));
+                assert_eq!(lamps.on.len(), 2);
+            }
+            assert!(!rotation.is_due(now + Duration::from_secs(59)));
+        }
+    }
+}
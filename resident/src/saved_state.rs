@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+
+// Decoys selected by the user, restored at the next start
+#[derive(Serialize, Deserialize, Default)]
+pub struct SavedState {
+    pub paused: bool,
+    pub enabled: Vec<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+impl SavedState {
+    /// Returns `None` on the first run, when nothing was saved yet.
+    pub fn load(path: &Path) -> io::Result<Option<SavedState>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let state: SavedState = toml::from_str(&text).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{0}: {1}", path.to_string_lossy(), e)
+        ))?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(path, text)
+    }
+}
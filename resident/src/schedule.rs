@@ -0,0 +1,308 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::menu_ids::MenuId;
+
+#[cfg(windows)]
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+    /// Day number as in SYSTEMTIME, 0 is Sunday.
+    #[must_use]
+    pub fn from_sunday_based(day: u16) -> Weekday {
+        Weekday::ALL[(usize::from(day) + 6) % 7]
+    }
+
+    fn previous(self) -> Weekday {
+        Weekday::ALL[(self as usize + 6) % 7]
+    }
+
+    fn parse(text: &str) -> Option<Weekday> {
+        Weekday::ALL.iter().copied().find(|d| format!("{0:?}", d).eq_ignore_ascii_case(text))
+    }
+}
+
+// Wall clock time of the machine, the schedules are written in it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LocalTime {
+    pub weekday: Weekday,
+    // Minutes since midnight
+    pub minute: u32,
+}
+
+// Source of the local time, tests use a fixed one
+pub trait Clock {
+    fn local_time(&self) -> LocalTime;
+}
+
+#[cfg(windows)]
+pub struct SystemClock;
+
+#[cfg(windows)]
+impl Clock for SystemClock {
+    fn local_time(&self) -> LocalTime {
+        let time = unsafe { GetLocalTime() };
+        LocalTime {
+            weekday: Weekday::from_sunday_based(time.wDayOfWeek),
+            minute: u32::from(time.wHour) * 60 + u32::from(time.wMinute),
+        }
+    }
+}
+
+// When an entry or the entries of a profile run, e.g.
+// schedule = [{ days = ["Mon", "Tue", "Wed", "Thu", "Fri"], from = "08:00", to = "18:00" }]
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(transparent)]
+pub struct Schedule {
+    windows: Vec<TimeWindow>,
+}
+
+// A window without `days` applies to every day. A window that ends before it starts runs over midnight,
+// into the next day.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "WindowSpec")]
+struct TimeWindow {
+    days: Vec<Weekday>,
+    from: u32,
+    to: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WindowSpec {
+    #[serde(default)]
+    days: Vec<String>,
+    from: String,
+    to: String,
+}
+
+#[derive(Debug)]
+struct WindowError(String);
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{0}", self.0)
+    }
+}
+
+impl TryFrom<WindowSpec> for TimeWindow {
+    type Error = WindowError;
+
+    fn try_from(spec: WindowSpec) -> Result<TimeWindow, WindowError> {
+        let mut days: Vec<Weekday> = Vec::with_capacity(spec.days.len());
+        for day in &spec.days {
+            days.push(Weekday::parse(day).ok_or_else(|| WindowError(format!("unknown day \"{0}\", use Mon to Sun", day)))?);
+        }
+        if days.is_empty() {
+            days = Weekday::ALL.to_vec();
+        }
+        let from = parse_time(&spec.from).filter(|m| *m < MINUTES_PER_DAY)
+            .ok_or_else(|| WindowError(format!("invalid start time \"{0}\", use HH:MM", spec.from)))?;
+        let to = parse_time(&spec.to)
+            .ok_or_else(|| WindowError(format!("invalid end time \"{0}\", use HH:MM", spec.to)))?;
+        if from == to {
+            return Err(WindowError(format!("time window {0} to {1} is empty", spec.from, spec.to)));
+        }
+        Ok(TimeWindow { days, from, to })
+    }
+}
+
+// "HH:MM", 24:00 is the end of the day
+fn parse_time(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    if hours.is_empty() || hours.len() > 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let minute = hours * 60 + minutes;
+    (minutes < 60 && minute <= MINUTES_PER_DAY).then_some(minute)
+}
+
+impl TimeWindow {
+    fn contains(&self, time: LocalTime) -> bool {
+        if self.from < self.to {
+            self.days.contains(&time.weekday) && self.from <= time.minute && time.minute < self.to
+        } else {
+            (self.days.contains(&time.weekday) && time.minute >= self.from)
+                || (self.days.contains(&time.weekday.previous()) && time.minute < self.to)
+        }
+    }
+}
+
+impl Schedule {
+    /// True if any window of the schedule covers `time`.
+    #[must_use]
+    pub fn is_active(&self, time: LocalTime) -> bool {
+        self.windows.iter().any(|w| w.contains(time))
+    }
+}
+
+// Turns schedules into enable and disable decisions. Only changes are reported, so an entry
+// switched by hand stays that way until its window opens or closes the next time.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    // Whether the window of each schedule-controlled entry was open at the last check
+    last: BTreeMap<MenuId, bool>,
+    last_time: Option<LocalTime>,
+}
+
+impl Scheduler {
+    pub const fn new() -> Scheduler {
+        Scheduler { last: BTreeMap::new(), last_time: None }
+    }
+
+    /// Checks `schedules` at the time of `clock` and returns the entries whose window opened (true)
+    /// or closed (false) since the last check. Entries checked for the first time report their
+    /// current state, entries no longer listed are forgotten.
+    pub fn changes<C: Clock>(&mut self, schedules: &[(MenuId, &Schedule)], clock: &C) -> Vec<(MenuId, bool)> {
+        let time = clock.local_time();
+        self.last_time = Some(time);
+        self.last.retain(|id, _| schedules.iter().any(|(i, _)| i == id));
+        let mut changes: Vec<(MenuId, bool)> = Vec::new();
+        for (id, schedule) in schedules {
+            let open = schedule.is_active(time);
+            if self.last.insert(*id, open) != Some(open) {
+                changes.push((*id, open));
+            }
+        }
+        changes
+    }
+
+    /// Records the current state of `schedules` without reporting it, e.g. at startup, where the
+    /// restored state already says what runs. Only windows opening or closing later are reported.
+    pub fn seed<C: Clock>(&mut self, schedules: &[(MenuId, &Schedule)], clock: &C) {
+        let _ignored = self.changes(schedules, clock);
+    }
+
+    /// Takes over the state of `other`, e.g. the scheduler before a reload, translating entry ids
+    /// with `map`. Entries `map` doesn't know are dropped.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn take_over<F: Fn(&MenuId) -> Option<MenuId>>(&mut self, other: &mut Scheduler, map: F) {
+        self.last_time = other.last_time;
+        for (id, open) in std::mem::take(&mut other.last) {
+            if let Some(id) = map(&id) {
+                self.last.insert(id, open);
+            }
+        }
+    }
+
+    /// Time of the last check, None before the first one.
+    #[must_use]
+    pub fn last_time(&self) -> Option<LocalTime> {
+        self.last_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct FixedClock(Cell<LocalTime>);
+
+    impl Clock for FixedClock {
+        fn local_time(&self) -> LocalTime {
+            self.0.get()
+        }
+    }
+
+    fn at(weekday: Weekday, hours: u32, minutes: u32) -> LocalTime {
+        LocalTime { weekday, minute: hours * 60 + minutes }
+    }
+
+    fn schedule(text: &str) -> Result<Schedule, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            schedule: Schedule,
+        }
+        toml::from_str::<Wrapper>(text).map(|w| w.schedule)
+    }
+
+    #[test]
+    fn windows_cover_days_and_hours() {
+        let office = schedule(r#"schedule = [{ days = ["Mon", "tue", "Wed", "Thu", "Fri"], from = "08:00", to = "18:30" }]"#).unwrap();
+        assert!(office.is_active(at(Weekday::Mon, 8, 0)));
+        assert!(office.is_active(at(Weekday::Fri, 18, 29)));
+        assert!(!office.is_active(at(Weekday::Fri, 18, 30)));
+        assert!(!office.is_active(at(Weekday::Sat, 12, 0)));
+
+        // Friday night runs into Saturday morning, but Sunday night doesn't start
+        let night = schedule(r#"schedule = [{ days = ["Fri"], from = "22:00", to = "06:00" }, { from = "12:00", to = "24:00", days = ["Sun"] }]"#).unwrap();
+        assert!(night.is_active(at(Weekday::Fri, 23, 0)));
+        assert!(night.is_active(at(Weekday::Sat, 5, 59)));
+        assert!(!night.is_active(at(Weekday::Sat, 23, 0)));
+        assert!(night.is_active(at(Weekday::Sun, 23, 59)));
+        assert!(!night.is_active(at(Weekday::Mon, 0, 0)));
+
+        let every_day = schedule(r#"schedule = [{ from = "0:00", to = "1:00" }]"#).unwrap();
+        assert!(every_day.is_active(at(Weekday::Wed, 0, 30)));
+
+        assert!(schedule(r#"schedule = [{ days = ["Monday"], from = "08:00", to = "18:00" }]"#).is_err());
+        assert!(schedule(r#"schedule = [{ from = "8", to = "18:00" }]"#).is_err());
+        assert!(schedule(r#"schedule = [{ from = "24:00", to = "01:00" }]"#).is_err());
+        assert!(schedule(r#"schedule = [{ from = "08:60", to = "18:00" }]"#).is_err());
+        assert!(schedule(r#"schedule = [{ from = "08:00", to = "08:00" }]"#).is_err());
+        assert!(schedule(r#"schedule = [{ from = "00:00", to = "24:00" }]"#).unwrap().is_active(at(Weekday::Sun, 23, 59)));
+        assert_eq!(Weekday::from_sunday_based(0), Weekday::Sun);
+        assert_eq!(Weekday::from_sunday_based(1), Weekday::Mon);
+    }
+
+    #[test]
+    fn reports_only_changes() {
+        let office = schedule(r#"schedule = [{ days = ["Mon"], from = "08:00", to = "18:00" }]"#).unwrap();
+        let clock = FixedClock(Cell::new(at(Weekday::Mon, 7, 59)));
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.last_time(), None);
+        let list = [(MenuId(100), &office), (MenuId(101), &office)];
+
+        // First check reports the current state
+        assert_eq!(scheduler.changes(&list, &clock), vec![(MenuId(100), false), (MenuId(101), false)]);
+        assert!(scheduler.changes(&list, &clock).is_empty());
+
+        clock.0.set(at(Weekday::Mon, 8, 0));
+        assert_eq!(scheduler.changes(&list, &clock), vec![(MenuId(100), true), (MenuId(101), true)]);
+        assert!(scheduler.changes(&list[..1], &clock).is_empty());
+        assert_eq!(scheduler.last_time(), Some(at(Weekday::Mon, 8, 0)));
+
+        // 101 was forgotten, so it starts over
+        clock.0.set(at(Weekday::Mon, 18, 0));
+        assert_eq!(scheduler.changes(&list, &clock), vec![(MenuId(100), false), (MenuId(101), false)]);
+    }
+
+    #[test]
+    fn seeded_scheduler_keeps_restored_state() {
+        let office = schedule(r#"schedule = [{ days = ["Mon", "Tue"], from = "08:00", to = "18:00" }]"#).unwrap();
+        // Restarted inside the window with the entry switched off by hand, nothing may turn it on
+        let clock = FixedClock(Cell::new(at(Weekday::Mon, 9, 0)));
+        let mut scheduler = Scheduler::new();
+        let list = [(MenuId(100), &office)];
+        scheduler.seed(&list, &clock);
+        assert_eq!(scheduler.last_time(), Some(at(Weekday::Mon, 9, 0)));
+        clock.0.set(at(Weekday::Mon, 9, 1));
+        assert!(scheduler.changes(&list, &clock).is_empty());
+
+        // The next real transitions apply as usual
+        clock.0.set(at(Weekday::Mon, 18, 0));
+        assert_eq!(scheduler.changes(&list, &clock), vec![(MenuId(100), false)]);
+        clock.0.set(at(Weekday::Tue, 8, 0));
+        assert_eq!(scheduler.changes(&list, &clock), vec![(MenuId(100), true)]);
+    }
+}
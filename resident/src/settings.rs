@@ -0,0 +1,252 @@
+use std::convert::TryFrom;
+use std::{fs, io, path::Path};
+
+use crate::pack::parse_key;
+use crate::config::{DEFAULT_PROCESS, KEEP_STUB_COPIES, LAUNCH_JITTER_MS, MAX_CONCURRENT_LAUNCHES, PROC_FOLDER, STOP_TIMEOUT_MS};
+
+// Runtime settings, read from the machine-wide settings file and then from the per-user one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    // Leave stub executables in proc/ when the process is stopped
+    pub keep_stub_copies: bool,
+    // Keys of the catalog entries enabled on the first run
+    pub default_process: Vec<String>,
+    // Subfolder of home_folder with the stub copies
+    pub proc_folder: String,
+    // Folder with the log and the stub copies
+    pub home_folder: String,
+    // Hex encoded Ed25519 public keys that may sign decoy packs
+    pub trusted_keys: Vec<String>,
+    // How long a stub may take to exit on request before it is killed, ms
+    pub stop_timeout_ms: u64,
+    // Longest random pause between the starts of two decoys, ms
+    pub launch_jitter_ms: u64,
+    // Decoys started at the same timer tick at most
+    pub max_concurrent_launches: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            keep_stub_copies: KEEP_STUB_COPIES,
+            default_process: DEFAULT_PROCESS.iter().map(|k| k.to_string()).collect(),
+            proc_folder: PROC_FOLDER.to_string(),
+            home_folder: std::env::var("TEMP").unwrap_or("C:/Temp".to_owned()) + "/des/",
+            trusted_keys: Vec::new(),
+            stop_timeout_ms: STOP_TIMEOUT_MS,
+            launch_jitter_ms: LAUNCH_JITTER_MS,
+            max_concurrent_launches: MAX_CONCURRENT_LAUNCHES,
+        }
+    }
+}
+
+impl Settings {
+    /// Applies the machine-wide settings file, then the per-user one, which overrides it.
+    /// Missing files are skipped. Returns warnings about suspicious content.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn load(&mut self, machine: &Path, user: &Path) -> io::Result<Vec<String>> {
+        let mut warnings: Vec<String> = Vec::new();
+        for (path, machine_wide) in [(machine, true), (user, false)] {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let source = path.to_string_lossy();
+            let merged = if machine_wide { self.merge(&text) } else { self.merge_user(&text) };
+            let file_warnings = merged
+                .map_err(|e| io::Error::new(e.kind(), format!("{0}: {1}", source, e)))?;
+            warnings.extend(file_warnings.into_iter().map(|w| format!("{0}: {1}", source, w)));
+        }
+        Ok(warnings)
+    }
+
+    /// Overrides the settings present in `text` of the machine-wide file, keeps the rest.
+    pub fn merge(&mut self, text: &str) -> io::Result<Vec<String>> {
+        self.merge_from(text, true)
+    }
+
+    /// Like `merge` for the per-user file. Only administrators decide who may sign decoy packs,
+    /// so `trusted_keys` is ignored with a warning.
+    pub fn merge_user(&mut self, text: &str) -> io::Result<Vec<String>> {
+        self.merge_from(text, false)
+    }
+
+    fn merge_from(&mut self, text: &str, machine_wide: bool) -> io::Result<Vec<String>> {
+        let table: toml::value::Table = toml::from_str(text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut warnings: Vec<String> = Vec::new();
+        for (key, value) in &table {
+            match key.as_str() {
+                "keep_stub_copies" => self.keep_stub_copies = as_bool(key, value)?,
+                "default_process" => self.default_process = as_string_list(key, value)?,
+                "proc_folder" => self.proc_folder = as_folder(key, value, &mut warnings)?,
+                "home_folder" => self.home_folder = as_folder(key, value, &mut warnings)?,
+                "trusted_keys" if !machine_wide => {
+                    warnings.push(format!("{0} is only read from the machine-wide settings, ignored", key))
+                }
+                "trusted_keys" => self.trusted_keys = as_key_list(key, value)?,
+                "stop_timeout_ms" => self.stop_timeout_ms = as_milliseconds(key, value)?,
+                "launch_jitter_ms" => self.launch_jitter_ms = as_milliseconds(key, value)?,
+                "max_concurrent_launches" => self.max_concurrent_launches = as_positive(key, value)?,
+                _ => warnings.push(format!("unknown setting {0}", key)),
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+fn type_error(key: &str, expected: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{0} must be {1}", key, expected))
+}
+
+fn as_bool(key: &str, value: &toml::Value) -> io::Result<bool> {
+    value.as_bool().ok_or_else(|| type_error(key, "a boolean"))
+}
+
+fn as_milliseconds(key: &str, value: &toml::Value) -> io::Result<u64> {
+    value.as_integer()
+        .and_then(|v| u64::try_from(v).ok())
+        .ok_or_else(|| type_error(key, "a non-negative number of milliseconds"))
+}
+
+fn as_positive(key: &str, value: &toml::Value) -> io::Result<usize> {
+    value.as_integer()
+        .and_then(|v| usize::try_from(v).ok())
+        .filter(|v| *v > 0)
+        .ok_or_else(|| type_error(key, "a positive number"))
+}
+
+fn as_string_list(key: &str, value: &toml::Value) -> io::Result<Vec<String>> {
+    let array = value.as_array().ok_or_else(|| type_error(key, "an array of strings"))?;
+    array.iter()
+        .map(|v| v.as_str().map(str::to_string).ok_or_else(|| type_error(key, "an array of strings")))
+        .collect()
+}
+
+fn as_key_list(key: &str, value: &toml::Value) -> io::Result<Vec<String>> {
+    let keys = as_string_list(key, value)?;
+    for k in &keys {
+        parse_key(k).map_err(|e| io::Error::new(e.kind(), format!("{0} has invalid key {1}: {2}", key, k, e)))?;
+    }
+    Ok(keys)
+}
+
+// Folder paths may refer to environment variables like %TEMP% and always end with a separator
+fn as_folder(key: &str, value: &toml::Value, warnings: &mut Vec<String>) -> io::Result<String> {
+    let text = value.as_str().ok_or_else(|| type_error(key, "a string"))?;
+    let mut folder = expand_env(text, |var| std::env::var(var).ok(), |var| {
+        warnings.push(format!("{0} refers to undefined variable %{1}%", key, var));
+    });
+    if !folder.ends_with('/') && !folder.ends_with('\\') {
+        folder.push('/');
+    }
+    Ok(folder)
+}
+
+// Replaces %VAR% with the value of the variable, undefined variables are left as is
+pub fn expand_env<F, W>(text: &str, lookup: F, mut on_undefined: W) -> String
+where F: Fn(&str) -> Option<String>, W: FnMut(&str) {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('%') {
+            Some(end) => {
+                let var = &after[..end];
+                match lookup(var) {
+                    Some(value) => result.push_str(&value),
+                    None => {
+                        on_undefined(var);
+                        result.push_str(&rest[start..start + end + 2]);
+                    }
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_file_overrides_machine_file() {
+        let mut settings = Settings::default();
+        let w1 = settings.merge("keep_stub_copies = false\nproc_folder = 'stubs'").unwrap();
+        let w2 = settings.merge_user("keep_stub_copies = true\ndefault_process = ['GUEST_VMWARE']").unwrap();
+        assert!(w1.is_empty() && w2.is_empty());
+        assert!(settings.keep_stub_copies);
+        assert_eq!(settings.proc_folder, "stubs/");
+        assert_eq!(settings.default_process, vec!["GUEST_VMWARE".to_string()]);
+    }
+
+    #[test]
+    fn defaults_match_constants() {
+        let settings = Settings::default();
+        assert_eq!(settings.keep_stub_copies, KEEP_STUB_COPIES);
+        assert_eq!(settings.proc_folder, PROC_FOLDER);
+        assert_eq!(settings.default_process.len(), DEFAULT_PROCESS.len());
+    }
+
+    #[test]
+    fn unknown_keys_are_warnings() {
+        let mut settings = Settings::default();
+        let warnings = settings.merge("keep_stub_copy = false\nkeep_stub_copies = false").unwrap();
+        assert_eq!(warnings, vec!["unknown setting keep_stub_copy".to_string()]);
+        assert!(!settings.keep_stub_copies);
+    }
+
+    #[test]
+    fn wrong_type_is_error() {
+        let mut settings = Settings::default();
+        let err = settings.merge("keep_stub_copies = 'yes'").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(settings.merge("default_process = ['A', 1]").is_err());
+        assert!(settings.merge("stop_timeout_ms = -1").is_err());
+        settings.merge("stop_timeout_ms = 500").unwrap();
+        assert_eq!(settings.stop_timeout_ms, 500);
+        assert!(settings.merge("max_concurrent_launches = 0").is_err());
+        settings.merge("max_concurrent_launches = 3\nlaunch_jitter_ms = 0").unwrap();
+        assert_eq!((settings.max_concurrent_launches, settings.launch_jitter_ms), (3, 0));
+    }
+
+    #[test]
+    fn trusted_keys_must_be_public_keys() {
+        let mut settings = Settings::default();
+        let key = "D04AB232742BB4AB3A1368BD4615E4E6D0224AB71A016BAF8520A332C9778737";
+        settings.merge(&format!("trusted_keys = ['{0}']", key)).unwrap();
+        assert_eq!(settings.trusted_keys, vec![key.to_string()]);
+        assert!(settings.merge("trusted_keys = ['D04AB232']").is_err());
+    }
+
+    #[test]
+    fn user_file_cant_trust_keys() {
+        let mut settings = Settings::default();
+        let key = "D04AB232742BB4AB3A1368BD4615E4E6D0224AB71A016BAF8520A332C9778737";
+        settings.merge(&format!("trusted_keys = ['{0}']", key)).unwrap();
+        let other = "3D4017C3E843895A92B70AA74D1B7EBC9C982CCF2EC4968CC0CD55F12AF4660C";
+        let warnings = settings.merge_user(&format!("trusted_keys = ['{0}']", other)).unwrap();
+        assert_eq!(warnings, vec!["trusted_keys is only read from the machine-wide settings, ignored".to_string()]);
+        assert_eq!(settings.trusted_keys, vec![key.to_string()]);
+        settings.merge_user("trusted_keys = []").unwrap();
+        assert_eq!(settings.trusted_keys, vec![key.to_string()]);
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        let lookup = |var: &str| if var == "TEMP" { Some("C:\\Tmp".to_string()) } else { None };
+        let mut undefined: Vec<String> = Vec::new();
+        let text = expand_env("%TEMP%\\des\\%NOPE%\\50%", lookup, |v| undefined.push(v.to_string()));
+        assert_eq!(text, "C:\\Tmp\\des\\%NOPE%\\50%");
+        assert_eq!(undefined, vec!["NOPE".to_string()]);
+    }
+}
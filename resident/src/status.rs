@@ -0,0 +1,163 @@
+// Status channel from des-stub to des-resident, opened by the stub before it creates its window.
+// A named pipe on Windows, a Unix socket elsewhere. One line per message:
+//
+//     HELLO <session token> <stub id>
+//     READY
+//     HEARTBEAT
+//     EVENT close
+//     EVENT session-end
+//     EVENT message 1025
+//
+// The resident answers a HELLO with the token of its session and an id it registered with
+// "WELCOME", anything else closes the channel. A stub that isn't welcomed exits, so it doesn't
+// run for whoever merely piped a well-formed request into it.
+//
+// des-stub includes this file as is, keep it free of resident dependencies.
+
+// Each side uses its half of the protocol
+#![allow(dead_code)]
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+// The stub sends a heartbeat when nothing else happened for this long
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+// A stub that stays silent for longer is considered hung
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(6);
+// How long a stub waits to be welcomed, connecting included
+pub const WELCOME_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Something happened to the stub that the resident may want to know about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StubEvent {
+    // Somebody asked the stub window to close
+    CloseRequested,
+    // The user session is ending
+    SessionEnding,
+    // A window message that only another process could have sent
+    ForeignMessage(u32),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+    Hello { token: String, stub_id: u32 },
+    Ready,
+    Heartbeat,
+    Event(StubEvent),
+    // The only message from the resident to the stub
+    Welcome,
+}
+
+impl Message {
+    /// Parses one line of the channel, unknown messages give None.
+    pub fn parse(line: &str) -> Option<Message> {
+        let mut words = line.split_whitespace();
+        let message = match (words.next()?, words.next(), words.next()) {
+            ("HELLO", Some(token), Some(stub_id)) => Message::Hello { token: token.to_string(), stub_id: stub_id.parse().ok()? },
+            ("READY", None, None) => Message::Ready,
+            ("HEARTBEAT", None, None) => Message::Heartbeat,
+            ("WELCOME", None, None) => Message::Welcome,
+            ("EVENT", Some("close"), None) => Message::Event(StubEvent::CloseRequested),
+            ("EVENT", Some("session-end"), None) => Message::Event(StubEvent::SessionEnding),
+            ("EVENT", Some("message"), Some(id)) => Message::Event(StubEvent::ForeignMessage(id.parse().ok()?)),
+            _ => return None,
+        };
+        if words.next().is_some() {
+            return None;
+        }
+        Some(message)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{0}", self)?;
+        out.flush()
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Hello { token, stub_id } => write!(f, "HELLO {0} {1}", token, stub_id),
+            Message::Ready => write!(f, "READY"),
+            Message::Heartbeat => write!(f, "HEARTBEAT"),
+            Message::Event(event) => write!(f, "EVENT {0}", event),
+            Message::Welcome => write!(f, "WELCOME"),
+        }
+    }
+}
+
+impl fmt::Display for StubEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StubEvent::CloseRequested => write!(f, "close"),
+            StubEvent::SessionEnding => write!(f, "session-end"),
+            StubEvent::ForeignMessage(id) => write!(f, "message {0}", id),
+        }
+    }
+}
+
+/// Waits for the resident to welcome the stub after its HELLO.
+pub fn read_welcome<R: BufRead>(input: &mut R) -> io::Result<()> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    match Message::parse(&line) {
+        Some(Message::Welcome) => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "resident didn't welcome the stub")),
+    }
+}
+
+/// Name of the status channel of a resident session, made of `nonce`, 32 random hex digits.
+/// Like the GUID-named pipes plenty of programs create, it gives away neither Des nor the session token.
+#[must_use]
+pub fn channel_name(nonce: &str) -> String {
+    let guid = format!("{{{0}-{1}-{2}-{3}-{4}}}", &nonce[..8], &nonce[8..12], &nonce[12..16], &nonce[16..20], &nonce[20..32]);
+    if cfg!(windows) {
+        format!("\\\\.\\pipe\\{0}", guid)
+    } else {
+        std::env::temp_dir().join(format!("{0}.sock", guid)).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_round_trip() {
+        let messages = vec![
+            Message::Hello { token: "00AA".to_string(), stub_id: 7 },
+            Message::Ready,
+            Message::Heartbeat,
+            Message::Event(StubEvent::CloseRequested),
+            Message::Event(StubEvent::SessionEnding),
+            Message::Event(StubEvent::ForeignMessage(1025)),
+            Message::Welcome,
+        ];
+        for message in messages {
+            assert_eq!(Message::parse(&message.to_string()), Some(message));
+        }
+        assert_eq!(Message::parse("HEARTBEAT\r\n"), Some(Message::Heartbeat));
+        assert_eq!(Message::parse("READY now"), None);
+        assert_eq!(Message::parse("HELLO 00AA x"), None);
+        assert_eq!(Message::parse("EVENT message"), None);
+        assert_eq!(Message::parse(""), None);
+    }
+
+    #[test]
+    fn stub_needs_welcome() {
+        let mut buffer: Vec<u8> = Vec::new();
+        Message::Welcome.write(&mut buffer).unwrap();
+        assert!(read_welcome(&mut buffer.as_slice()).is_ok());
+        assert!(read_welcome(&mut "READY\n".as_bytes()).is_err());
+        // The resident closed the channel
+        assert!(read_welcome(&mut "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn channel_name_is_neutral() {
+        let name = channel_name("00112233445566778899AABBCCDDEEFF");
+        assert!(name.ends_with("{00112233-4455-6677-8899-AABBCCDDEEFF}") || name.ends_with("{00112233-4455-6677-8899-AABBCCDDEEFF}.sock"));
+        assert!(!name.to_lowercase().contains("des-"));
+    }
+}
@@ -0,0 +1,355 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::os::windows::io::FromRawHandle;
+use std::sync::{mpsc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::Foundation::*,
+    Win32::Security::Cryptography::{BCryptGenRandom, BCRYPT_ALG_HANDLE, BCRYPT_USE_SYSTEM_PREFERRED_RNG},
+    Win32::System::Pipes::CreatePipe,
+    Win32::System::Threading::*,
+};
+
+use crate::convert::to_utf16;
+use crate::handshake::{self, Persona, Request, StatusChannel};
+use crate::lifetime;
+use crate::monitor::StubMonitor;
+use crate::status::{self, StubEvent};
+
+// How long a stub may take to create its window and confirm the start
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Running stub. Unlike std::process::Child, it is started with a command line of our choice,
+// including argv[0], so tools reading the PEB or Win32_Process see a realistic one.
+pub struct StubProcess {
+    process: HANDLE,
+    exit_code: Option<u32>,
+    // Stdin pipe of the stub, stays open until the stub is asked to exit
+    requests: File,
+    // Id of the stub on the status channel
+    stub_id: u32,
+    started: Instant,
+    // Answer of the stub to the start request, until `check_started` has seen it
+    handshake: Option<mpsc::Receiver<io::Result<()>>>,
+}
+
+impl StubProcess {
+    /// Runs the executable at `path` with the literal `command_line`, hands it the session token,
+    /// `persona` and its status channel through its stdin pipe. Returns right away, `check_started`
+    /// tells whether the stub confirmed the start.
+    pub fn spawn(path: &str, command_line: &str, persona: &Persona) -> io::Result<StubProcess> {
+        let monitor = session_monitor()?;
+        let token = session_token()?;
+        let application = to_utf16(path);
+        // CreateProcessW may modify the command line buffer
+        let mut command_line = to_utf16(command_line);
+        let (stdin_read, stdin_write) = create_pipe(true)?;
+        let (stdout_write, stdout_read) = match create_pipe(false) {
+            Ok(pipe) => pipe,
+            Err(e) => {
+                unsafe { CloseHandle(stdin_read); CloseHandle(stdin_write); }
+                return Err(e);
+            }
+        };
+        let created = create_process(&application, &mut command_line, stdin_read, stdout_write);
+        // The stub has its own copies now, or failed to start
+        unsafe {
+            CloseHandle(stdin_read);
+            CloseHandle(stdout_write);
+        }
+        // Files close the parent ends when dropped
+        let requests = unsafe { File::from_raw_handle(stdin_write.0 as _) };
+        let answers = unsafe { File::from_raw_handle(stdout_read.0 as _) };
+        let process_info = created?;
+        if let Err(_e) = lifetime::assign_to_job(process_info.hProcess) {
+            // Still exits once the pipe breaks, unless it hangs
+            #[cfg(feature = "logger")] warn!("Can't put the stub into the job, it may outlive a crash: {0}", _e);
+        }
+        let resumed = match unsafe { ResumeThread(process_info.hThread) } {
+            u32::MAX => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        };
+        unsafe { CloseHandle(process_info.hThread) };
+        let mut stub = StubProcess {
+            process: process_info.hProcess,
+            exit_code: None,
+            requests,
+            stub_id: monitor.register(),
+            started: Instant::now(),
+            handshake: None,
+        };
+        if let Err(e) = resumed {
+            let _ = stub.kill();
+            return Err(e);
+        }
+
+        let request = Request {
+            token: token.to_string(),
+            persona: persona.clone(),
+            status: Some(StatusChannel { name: monitor.name().to_string(), stub_id: stub.stub_id }),
+        };
+        if let Err(e) = request.write(&mut stub.requests) {
+            let _ = stub.kill();
+            return Err(e);
+        }
+        stub.handshake = Some(read_started(answers, token));
+        Ok(stub)
+    }
+
+    /// True once the stub confirmed the start, false while it still has time to.
+    /// Fails if it refused, died or didn't answer in time; the caller kills it then.
+    pub fn check_started(&mut self) -> io::Result<bool> {
+        let Some(receiver) = &self.handshake else { return Ok(true) };
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) if self.started.elapsed() < HANDSHAKE_TIMEOUT => return Ok(false),
+            Err(mpsc::TryRecvError::Empty) => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "Stub didn't confirm the start in time."))
+            }
+            Err(mpsc::TryRecvError::Disconnected) => Err(io::Error::other("Stub didn't confirm the start.")),
+        };
+        self.handshake = None;
+        result.map(|_| true)
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        if self.try_wait()?.is_some() {
+            return Ok(());
+        }
+        unsafe { TerminateProcess(self.process, 1) }.ok()?;
+        Ok(())
+    }
+
+    /// Asks the stub to exit on its own, `stop` waits for it.
+    pub fn request_exit(&mut self) -> io::Result<()> {
+        handshake::write_shutdown(&mut self.requests)
+    }
+
+    /// Gives the stub until `deadline` to exit after `request_exit`, then kills it.
+    /// Either way the stub is reaped and its exit code returned.
+    pub fn stop(&mut self, deadline: Instant) -> io::Result<u32> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        // Just below INFINITE
+        let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX - 1);
+        if let Some(code) = self.wait_for(timeout_ms)? {
+            return Ok(code);
+        }
+        #[cfg(feature = "logger")] debug!("Stub didn't exit in time, killing it.");
+        self.kill()?;
+        self.wait()
+    }
+
+    /// Blocks until the stub exits and returns its exit code.
+    pub fn wait(&mut self) -> io::Result<u32> {
+        // u32::MAX is INFINITE
+        self.wait_for(u32::MAX)?.ok_or_else(|| io::Error::other("Stub is still running."))
+    }
+
+    /// Returns the exit code if the stub has exited, without blocking.
+    pub fn try_wait(&mut self) -> io::Result<Option<u32>> {
+        self.wait_for(0)
+    }
+
+    fn wait_for(&mut self, timeout_ms: u32) -> io::Result<Option<u32>> {
+        if self.exit_code.is_some() {
+            return Ok(self.exit_code);
+        }
+        match unsafe { WaitForSingleObject(self.process, timeout_ms) } {
+            WAIT_OBJECT_0 => {
+                let mut code: u32 = 0;
+                unsafe { GetExitCodeProcess(self.process, &mut code) }.ok()?;
+                self.exit_code = Some(code);
+            }
+            WAIT_TIMEOUT => {}
+            _ => return Err(io::Error::last_os_error()),
+        }
+        Ok(self.exit_code)
+    }
+
+    /// When the stub was created and when it exited, only meaningful after it has exited.
+    pub fn lifetime(&self) -> io::Result<(SystemTime, SystemTime)> {
+        let mut created = FILETIME::default();
+        let mut exited = FILETIME::default();
+        let mut kernel = FILETIME::default();
+        let mut user = FILETIME::default();
+        unsafe { GetProcessTimes(self.process, &mut created, &mut exited, &mut kernel, &mut user) }.ok()?;
+        Ok((to_system_time(created), to_system_time(exited)))
+    }
+
+    /// True if the stub is still running but stopped reporting, after it had time to connect.
+    /// A stub that never connected to the status channel is judged by its process alone.
+    #[must_use]
+    pub fn is_hung(&self) -> bool {
+        session_monitor().is_ok_and(|m| m.is_hung(self.stub_id, self.started, Instant::now()))
+    }
+
+    /// Events the stub reported since the last call.
+    pub fn take_events(&self) -> Vec<StubEvent> {
+        session_monitor().map(|m| m.take_events(self.stub_id)).unwrap_or_default()
+    }
+}
+
+impl Drop for StubProcess {
+    fn drop(&mut self) {
+        if let Ok(monitor) = session_monitor() {
+            monitor.forget(self.stub_id);
+        }
+        unsafe { CloseHandle(self.process) };
+    }
+}
+
+// FILETIME counts 100 ns intervals since 1601-01-01
+fn to_system_time(time: FILETIME) -> SystemTime {
+    const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+    let ticks = (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime);
+    UNIX_EPOCH + Duration::from_nanos(ticks.saturating_sub(UNIX_EPOCH_TICKS).saturating_mul(100))
+}
+
+// Pipe between the resident and a stub. Only the end the stub gets is inheritable, so the stub can't
+// hold the resident's end open and sees end of file once the resident is gone.
+// Returns (stub end, resident end).
+fn create_pipe(stub_reads: bool) -> io::Result<(HANDLE, HANDLE)> {
+    let mut read = HANDLE::default();
+    let mut write = HANDLE::default();
+    unsafe { CreatePipe(&mut read, &mut write, None, 0) }.ok()?;
+    let (stub_end, resident_end) = if stub_reads { (read, write) } else { (write, read) };
+    if let Err(e) = unsafe { SetHandleInformation(stub_end, HANDLE_FLAG_INHERIT.0, HANDLE_FLAG_INHERIT) }.ok() {
+        unsafe { CloseHandle(read); CloseHandle(write); }
+        return Err(e.into());
+    }
+    Ok((stub_end, resident_end))
+}
+
+// Creates the process suspended, with `stdin` and `stdout` as its standard handles. The handle list
+// makes these two the only handles it inherits, other stubs' pipe ends stay out even if they are
+// inheritable while it starts.
+fn create_process(application: &[u16], command_line: &mut [u16], stdin: HANDLE, stdout: HANDLE)
+-> io::Result<PROCESS_INFORMATION> {
+    let mut size: usize = 0;
+    // Only asks for the size, fails by design
+    unsafe { InitializeProcThreadAttributeList(LPPROC_THREAD_ATTRIBUTE_LIST::default(), 1, 0, &mut size) };
+    // usize keeps the list aligned
+    let mut buffer: Vec<usize> = vec![0; size / std::mem::size_of::<usize>() + 1];
+    let attributes = LPPROC_THREAD_ATTRIBUTE_LIST(buffer.as_mut_ptr().cast());
+    unsafe { InitializeProcThreadAttributeList(attributes, 1, 0, &mut size) }.ok()?;
+    let handles = [stdin, stdout];
+    let created = unsafe {
+        UpdateProcThreadAttribute(
+            attributes,
+            0,
+            PROC_THREAD_ATTRIBUTE_HANDLE_LIST as usize,
+            Some(handles.as_ptr().cast()),
+            std::mem::size_of_val(&handles),
+            None,
+            None,
+        ).ok()
+    }.and_then(|_| {
+        let startup_info = STARTUPINFOEXW {
+            StartupInfo: STARTUPINFOW {
+                cb: std::mem::size_of::<STARTUPINFOEXW>() as u32,
+                dwFlags: STARTF_USESTDHANDLES,
+                hStdInput: stdin,
+                hStdOutput: stdout,
+                ..Default::default()
+            },
+            lpAttributeList: attributes,
+        };
+        let mut process_info = PROCESS_INFORMATION::default();
+        unsafe {
+            CreateProcessW(
+                PCWSTR(application.as_ptr()),
+                PWSTR(command_line.as_mut_ptr()),
+                None,
+                None,
+                true,
+                // Resumed once it is in the job, so it can't spawn anything outside of it
+                CREATE_SUSPENDED | EXTENDED_STARTUPINFO_PRESENT,
+                None,
+                PCWSTR::null(),
+                &startup_info.StartupInfo,
+                &mut process_info,
+            ).ok()
+        }.map(|_| process_info)
+    });
+    unsafe { DeleteProcThreadAttributeList(attributes) };
+    Ok(created?)
+}
+
+// Reads the answer on a separate thread, so the window thread doesn't wait for the stub and
+// anonymous pipes need no read timeout. Killing the stub closes the pipe and ends the thread.
+fn read_started(answers: File, token: &'static str) -> mpsc::Receiver<io::Result<()>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(handshake::read_started(&mut BufReader::new(answers), token));
+    });
+    receiver
+}
+
+/// Random token of this resident session, only stubs started by this resident know it.
+pub fn session_token() -> io::Result<&'static str> {
+    static TOKEN: OnceLock<String> = OnceLock::new();
+    if let Some(token) = TOKEN.get() {
+        return Ok(token);
+    }
+    let token = random_hex(handshake::TOKEN_LEN / 2)?;
+    Ok(TOKEN.get_or_init(|| token))
+}
+
+/// Status channel of this resident session, opened with the first stub.
+pub fn session_monitor() -> io::Result<&'static StubMonitor> {
+    static MONITOR: OnceLock<StubMonitor> = OnceLock::new();
+    if let Some(monitor) = MONITOR.get() {
+        return Ok(monitor);
+    }
+    // The name is public, so it comes from its own nonce rather than from the token
+    let monitor = StubMonitor::start(&status::channel_name(&random_hex(16)?), session_token()?)?;
+    Ok(MONITOR.get_or_init(|| monitor))
+}
+
+// `len` bytes from the system RNG as hex digits
+fn random_hex(len: usize) -> io::Result<String> {
+    let mut bytes = vec![0u8; len];
+    unsafe { BCryptGenRandom(BCRYPT_ALG_HANDLE::default(), &mut bytes, BCRYPT_USE_SYSTEM_PREFERRED_RNG) }?;
+    Ok(bytes.iter().map(|v| format!("{:02X}", v)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // findstr reads its stdin until end of file, like a stub waits for SHUTDOWN
+    fn start_findstr() -> (PROCESS_INFORMATION, HANDLE) {
+        let application = to_utf16(&(std::env::var("SystemRoot").unwrap() + "\\System32\\findstr.exe"));
+        let mut command_line = to_utf16("findstr x");
+        let (stdin_read, stdin_write) = create_pipe(true).unwrap();
+        let (stdout_write, stdout_read) = create_pipe(false).unwrap();
+        let process_info = create_process(&application, &mut command_line, stdin_read, stdout_write).unwrap();
+        unsafe {
+            CloseHandle(stdin_read);
+            CloseHandle(stdout_write);
+            CloseHandle(stdout_read);
+            ResumeThread(process_info.hThread);
+            CloseHandle(process_info.hThread);
+        }
+        (process_info, stdin_write)
+    }
+
+    #[test]
+    fn child_exits_once_resident_end_closes() {
+        let (first, first_stdin) = start_findstr();
+        // Started while the first pipe is open, it must not hold the first stdin open
+        let (second, second_stdin) = start_findstr();
+        unsafe { CloseHandle(first_stdin) };
+        assert_eq!(unsafe { WaitForSingleObject(first.hProcess, 5000) }, WAIT_OBJECT_0);
+        assert_eq!(unsafe { WaitForSingleObject(second.hProcess, 0) }, WAIT_TIMEOUT);
+        unsafe { CloseHandle(second_stdin) };
+        assert_eq!(unsafe { WaitForSingleObject(second.hProcess, 5000) }, WAIT_OBJECT_0);
+        unsafe {
+            CloseHandle(first.hProcess);
+            CloseHandle(second.hProcess);
+        }
+    }
+}
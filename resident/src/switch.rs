@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use crate::menu_ids::MenuId;
 
 pub trait Switch {
@@ -8,4 +10,122 @@ pub trait Switch {
 
     #[must_use]
     fn is_enabled(&self, id: &MenuId) -> bool;
-}
\ No newline at end of file
+
+    /// Enables every item of `menu_items`; a failing item doesn't keep the others from being enabled.
+    fn enable_all(&mut self, menu_items: &[MenuId]) -> BatchResult<Self::ErrorType> {
+        let mut result = BatchResult::new();
+        for id in menu_items {
+            result.add(*id, self.enable(id));
+        }
+        result
+    }
+
+    /// Disables every item of `menu_items`; a failing item doesn't keep the others from being disabled.
+    fn disable_all(&mut self, menu_items: &[MenuId]) -> BatchResult<Self::ErrorType> {
+        let mut result = BatchResult::new();
+        for id in menu_items {
+            result.add(*id, self.disable(id));
+        }
+        result
+    }
+}
+
+// Per item outcome of enable_all and disable_all
+#[derive(Debug)]
+pub struct BatchResult<E> {
+    pub succeeded: Vec<MenuId>,
+    pub failed: Vec<(MenuId, E)>,
+}
+
+impl<E> BatchResult<E> {
+    pub const fn new() -> BatchResult<E> {
+        BatchResult { succeeded: Vec::new(), failed: Vec::new() }
+    }
+
+    pub fn add(&mut self, id: MenuId, result: std::result::Result<(), E>) {
+        match result {
+            Ok(()) => self.succeeded.push(id),
+            Err(e) => self.failed.push((id, e)),
+        }
+    }
+
+    /// Adds the outcomes of another batch, e.g. the starts after the stops of a profile switch.
+    pub fn merge(&mut self, other: BatchResult<E>) {
+        self.succeeded.extend(other.succeeded);
+        self.failed.extend(other.failed);
+    }
+
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl<E: Display> BatchResult<E> {
+    /// Every failure on its own line, items named by `name`. None if nothing failed.
+    #[must_use]
+    pub fn summary<F: Fn(&MenuId) -> String>(&self, name: F) -> Option<String> {
+        if self.is_ok() {
+            return None;
+        }
+        let total = self.succeeded.len() + self.failed.len();
+        let mut text = format!("{0} of {1} failed:", self.failed.len(), total);
+        for (id, e) in &self.failed {
+            text += &format!("\n{0}: {1}", name(id), e);
+        }
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Test double for the users of Switch, items matching `broken` can't be enabled
+    pub(crate) struct Lamps {
+        pub(crate) on: Vec<MenuId>,
+        pub(crate) broken: fn(&MenuId) -> bool,
+    }
+
+    impl Switch for Lamps {
+        type ErrorType = String;
+
+        fn enable(&mut self, id: &MenuId) -> std::result::Result<(), String> {
+            if (self.broken)(id) {
+                return Err("broken".to_string());
+            }
+            self.on.push(*id);
+            Ok(())
+        }
+
+        fn disable(&mut self, id: &MenuId) -> std::result::Result<(), String> {
+            self.on.retain(|i| i != id);
+            Ok(())
+        }
+
+        fn is_enabled(&self, id: &MenuId) -> bool {
+            self.on.contains(id)
+        }
+    }
+
+    #[test]
+    fn batch_attempts_every_item() {
+        // Items with odd ids can't be switched
+        let mut lamps = Lamps { on: Vec::new(), broken: |id| id.0 % 2 == 1 };
+        let ids = [MenuId(2), MenuId(3), MenuId(4), MenuId(5)];
+        let result = lamps.enable_all(&ids);
+        assert_eq!(result.succeeded, vec![MenuId(2), MenuId(4)]);
+        assert_eq!(result.failed.iter().map(|(id, _)| *id).collect::<Vec<MenuId>>(), vec![MenuId(3), MenuId(5)]);
+        assert!(lamps.is_enabled(&MenuId(4)));
+        assert_eq!(
+            result.summary(|id| format!("Lamp {0}", id.0)).unwrap(),
+            "2 of 4 failed:\nLamp 3: broken\nLamp 5: broken"
+        );
+
+        let mut result = lamps.disable_all(&[MenuId(2), MenuId(4)]);
+        assert!(result.is_ok() && result.summary(|_| String::new()).is_none());
+        result.merge(lamps.enable_all(&[MenuId(7)]));
+        assert!(!result.is_ok());
+        assert!(lamps.on.is_empty());
+    }
+}
@@ -40,6 +40,12 @@ impl TrayMenuState {
         Ok(())
     }
 
+    /// Rebuilds the active menu so it reflects the current `menu_state`.
+    pub unsafe fn refresh(&mut self, menu_state: &MenuState, autostart: bool) -> windows::core::Result<()> {
+        self.menu_tray_active.destroy();
+        self.menu_tray_active.create_menu_active(menu_state, autostart)
+    }
+
     pub unsafe fn destroy(&mut self) {
         self.menu_tray_active.destroy();
         self.menu_tray_paused.destroy();
@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::catalog::{Catalog, Process};
+
+// Limit of RegisterClassEx for class names
+const MAX_CLASS_NAME: usize = 256;
+// Limit of CreateProcess for command lines, including the terminating null
+const MAX_COMMAND_LINE: usize = 32768;
+// Characters Windows doesn't allow in file names, control characters are checked separately
+const FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+// Device names are reserved with any extension, e.g. "CON.exe"
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Single problem found in the catalog
+#[derive(Debug, PartialEq, Eq)]
+pub struct Issue {
+    // Id of the entry, category or profile the problem belongs to
+    pub owner: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{0}: {1}", self.owner, self.message)
+    }
+}
+
+/// Checks the catalog and returns all found problems, empty vector means the catalog is valid.
+pub fn validate(catalog: &Catalog) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+    let mut report = |owner: &str, message: String| issues.push(Issue { owner: owner.to_string(), message });
+
+    let mut category_ids: Vec<&str> = Vec::new();
+    let mut entry_ids: Vec<&str> = Vec::new();
+    // Lowercase process name => (entry id, original name)
+    let mut process_owners: BTreeMap<String, (&str, &str)> = BTreeMap::new();
+
+    for category in &catalog.categories {
+        if category.id.is_empty() {
+            report(&category.name, "category id is empty".to_string());
+        } else if category_ids.contains(&category.id.as_str()) {
+            report(&category.id, "duplicate category id".to_string());
+        }
+        category_ids.push(&category.id);
+
+        for entry in &category.entries {
+            if entry.id.is_empty() {
+                report(&entry.name, "entry id is empty".to_string());
+            } else if entry_ids.contains(&entry.id.as_str()) {
+                report(&entry.id, "duplicate entry id".to_string());
+            }
+            entry_ids.push(&entry.id);
+
+            if entry.name.is_empty() {
+                report(&entry.id, "entry name is empty".to_string());
+            }
+            if entry.processes.is_empty() {
+                report(&entry.id, "entry has no processes".to_string());
+            }
+
+            for Process { name: process, window_class, window_title, command_line } in &entry.processes {
+                if let Some(problem) = check_file_name(process) {
+                    report(&entry.id, format!("process \"{0}\" {1}", process, problem));
+                }
+                if let Some(problem) = window_class.as_deref().and_then(check_window_class) {
+                    report(&entry.id, format!("process \"{0}\" {1}", process, problem));
+                }
+                if window_title.as_deref().is_some_and(|t| t.contains('\0')) {
+                    report(&entry.id, format!("process \"{0}\" has a window title with a NUL character", process));
+                }
+                if let Some(problem) = command_line.as_deref().and_then(|c| check_command_line(process, c)) {
+                    report(&entry.id, format!("process \"{0}\" {1}", process, problem));
+                }
+                // All stubs share the proc/ folder, and Windows file names are case insensitive
+                match process_owners.get(&process.to_lowercase()) {
+                    Some((owner, name)) if *owner == entry.id => {
+                        report(&entry.id, format!("process \"{0}\" is listed twice as \"{1}\"", process, name));
+                    }
+                    Some((owner, name)) => {
+                        report(&entry.id, format!("process \"{0}\" collides with \"{1}\" of {2}", process, name, owner));
+                    }
+                    None => {
+                        process_owners.insert(process.to_lowercase(), (entry.id.as_str(), process.as_str()));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut profile_ids: Vec<&str> = Vec::new();
+    for profile in &catalog.profiles {
+        if profile_ids.contains(&profile.id.as_str()) {
+            report(&profile.id, "duplicate profile id".to_string());
+        }
+        profile_ids.push(&profile.id);
+        for key in &profile.entries {
+            if !entry_ids.contains(&key.as_str()) {
+                report(&profile.id, format!("profile refers to unknown entry {0}", key));
+            }
+        }
+        if let Some(rotation) = &profile.rotation {
+            if rotation.count == 0 || rotation.count > profile.entries.len() {
+                report(&profile.id, format!("rotation count must be between 1 and {0}", profile.entries.len()));
+            }
+            if rotation.min_interval_s == 0 || rotation.min_interval_s > rotation.max_interval_s {
+                report(&profile.id, "rotation intervals must satisfy 0 < min_interval_s <= max_interval_s".to_string());
+            }
+            // The rotation decides which entries run, a schedule would fight it
+            if profile.schedule.is_some() {
+                report(&profile.id, "a rotating profile can't have a schedule".to_string());
+            }
+        }
+    }
+
+    issues
+}
+
+// Describes why `class` can't be registered with RegisterClassEx
+fn check_window_class(class: &str) -> Option<&'static str> {
+    if class.is_empty() {
+        return Some("has an empty window class");
+    }
+    if class.contains('\0') {
+        return Some("has a window class with a NUL character");
+    }
+    if class.encode_utf16().count() > MAX_CLASS_NAME {
+        return Some("has a window class longer than 256 characters");
+    }
+    None
+}
+
+// Describes why `command_line` can't start the stub `name`
+fn check_command_line(name: &str, command_line: &str) -> Option<&'static str> {
+    if command_line.contains('\0') {
+        return Some("has a command line with a NUL character");
+    }
+    if command_line.encode_utf16().count() >= MAX_COMMAND_LINE {
+        return Some("has a command line longer than 32767 characters");
+    }
+    // argv[0] is either quoted or ends at the first space
+    let trimmed = command_line.trim_start();
+    let program = match trimmed.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or(quoted),
+        None => trimmed.split(char::is_whitespace).next().unwrap_or(trimmed),
+    };
+    let file_name = program.rsplit(['\\', '/']).next().unwrap_or(program);
+    if !file_name.eq_ignore_ascii_case(name) {
+        return Some("has a command line that doesn't start with the process name");
+    }
+    None
+}
+
+// Describes why `name` can't be a file name on Windows
+fn check_file_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        return Some("has empty name");
+    }
+    if name.chars().any(|c| c.is_control() || FORBIDDEN_CHARS.contains(&c)) {
+        return Some("contains characters forbidden in file names");
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with a dot or space");
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Some("uses a reserved device name");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Catalog {
+        toml::from_str(text).unwrap()
+    }
+
+    fn owners(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|i| i.owner.as_str()).collect()
+    }
+
+    #[test]
+    fn case_insensitive_collision_between_entries() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "AV"
+            name = "Antivirus"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["MAILDISP.EXE"]
+            [[category.entry]]
+            id = "B"
+            name = "B"
+            processes = ["maildisp.exe"]
+        "#);
+        let issues = validate(&catalog);
+        assert_eq!(owners(&issues), vec!["B"]);
+        assert!(issues[0].message.contains("MAILDISP.EXE"));
+        assert!(issues[0].message.contains("of A"));
+    }
+
+    #[test]
+    fn duplicate_within_entry() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "AV"
+            name = "Antivirus"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["a.exe", "A.exe"]
+        "#);
+        assert_eq!(owners(&validate(&catalog)), vec!["A"]);
+    }
+
+    #[test]
+    fn illegal_names_are_reported_per_entry() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["a?.exe", "b.exe."]
+            [[category.entry]]
+            id = "B"
+            name = "B"
+            processes = ["CON", "nul.exe", "Lpt1.txt", "c:d.exe", "tab\t.exe", ""]
+            [[category.entry]]
+            id = "C"
+            name = "C"
+            processes = ["console.exe", "con_.exe", "com10.exe"]
+        "#);
+        let issues = validate(&catalog);
+        assert_eq!(owners(&issues), vec!["A", "A", "B", "B", "B", "B", "B", "B"]);
+    }
+
+    #[test]
+    fn invalid_window_class() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "D"
+            name = "Debugger"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = [{ name = "a.exe", window_class = "" }, { name = "b.exe", window_class = "OLLYDBG" }]
+        "#);
+        assert_eq!(owners(&validate(&catalog)), vec!["A"]);
+    }
+
+    #[test]
+    fn command_line_starts_with_process() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = [
+                { name = "vmtoolsd.exe", command_line = "vmtoolsd.exe -n vmusr" },
+                { name = "procexp64.exe", command_line = '"%ProgramFiles%\\Sysinternals\\PROCEXP64.EXE" /t' },
+                { name = "a.exe", command_line = "C:\\a.exe" },
+            ]
+            [[category.entry]]
+            id = "B"
+            name = "B"
+            processes = [
+                { name = "b.exe", command_line = "other.exe -b" },
+                { name = "c.exe", command_line = '"C:\\Program Files\\c.exe' },
+                { name = "d.exe", command_line = "" },
+            ]
+        "#);
+        assert_eq!(owners(&validate(&catalog)), vec!["B", "B"]);
+    }
+
+    #[test]
+    fn duplicate_ids_and_empty_entries() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = ""
+            processes = []
+            [[category]]
+            id = "T"
+            name = "More tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["a.exe"]
+        "#);
+        let issues = validate(&catalog);
+        assert_eq!(owners(&issues), vec!["A", "A", "T", "A"]);
+    }
+
+    #[test]
+    fn profile_with_unknown_entry() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["a.exe"]
+            [[profile]]
+            id = "P"
+            name = "Profile"
+            entries = ["A", "MISSING"]
+            [[profile]]
+            id = "P"
+            name = "Same profile"
+            entries = []
+        "#);
+        let issues = validate(&catalog);
+        assert_eq!(owners(&issues), vec!["P", "P"]);
+        assert!(issues[0].message.contains("MISSING"));
+    }
+
+    #[test]
+    fn rotation_must_fit_the_profile() {
+        let catalog = parse(r#"
+            version = 1
+            [[category]]
+            id = "T"
+            name = "Tools"
+            [[category.entry]]
+            id = "A"
+            name = "A"
+            processes = ["a.exe"]
+            [[category.entry]]
+            id = "B"
+            name = "B"
+            processes = ["b.exe"]
+            [[profile]]
+            id = "OK"
+            name = "Fine"
+            entries = ["A", "B"]
+            rotation = { count = 1, min_interval_s = 60, max_interval_s = 60 }
+            [[profile]]
+            id = "BIG"
+            name = "Too many"
+            entries = ["A", "B"]
+            rotation = { count = 3, min_interval_s = 60, max_interval_s = 30 }
+            [[profile]]
+            id = "TIMED"
+            name = "Rotating on schedule"
+            entries = ["A", "B"]
+            rotation = { count = 1, min_interval_s = 60, max_interval_s = 60 }
+            schedule = [{ from = "08:00", to = "18:00" }]
+        "#);
+        let issues = validate(&catalog);
+        assert_eq!(owners(&issues), vec!["BIG", "BIG", "TIMED"]);
+        assert!(issues[0].message.contains("between 1 and 2"));
+    }
+}
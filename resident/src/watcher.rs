@@ -0,0 +1,70 @@
+use std::{fs, path::PathBuf, time::SystemTime};
+
+// Modification time and size of a file, None if the file doesn't exist
+type Snapshot = Option<(Option<SystemTime>, u64)>;
+
+// Detects changes of config files by polling their metadata
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Snapshot)>,
+}
+
+impl FileWatcher {
+    pub const fn new() -> FileWatcher {
+        FileWatcher { files: Vec::new() }
+    }
+
+    /// Starts watching `path`; the current state of the file is the baseline.
+    pub fn watch(&mut self, path: PathBuf) {
+        let snapshot = take_snapshot(&path);
+        self.files.push((path, snapshot));
+    }
+
+    /// Returns true if any watched file was created, modified or deleted since the last call.
+    pub fn poll(&mut self) -> bool {
+        let mut is_changed = false;
+        for (path, snapshot) in &mut self.files {
+            let current = take_snapshot(path);
+            if current != *snapshot {
+                *snapshot = current;
+                is_changed = true;
+            }
+        }
+        is_changed
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+fn take_snapshot(path: &PathBuf) -> Snapshot {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_create_modify_delete() {
+        let path = std::env::temp_dir().join(format!("des-watcher-{0}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut watcher = FileWatcher::new();
+        watcher.watch(path.clone());
+        assert!(!watcher.poll());
+
+        fs::write(&path, "version = 1").unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        fs::write(&path, "version = 1\n# edited").unwrap();
+        assert!(watcher.poll());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+    }
+}
@@ -1,32 +1,64 @@
 #![windows_subsystem = "windows"]
 
+#[cfg(windows)]
 use windows::{
     w,
-    // core::*,
+    core::PCWSTR,
     Win32::Foundation::*,
     Win32::System::LibraryLoader::GetModuleHandleW,
+    Win32::System::Pipes::WaitNamedPipeW,
+    Win32::System::Threading::GetCurrentThreadId,
     Win32::UI::WindowsAndMessaging::*,
 };
 
+#[cfg(windows)]
 #[macro_use]
 mod macros;
 
+// Shared with des-resident, so both sides speak the same protocol
+#[cfg(windows)]
+#[path = "../../resident/src/handshake.rs"]
+mod handshake;
+#[cfg(windows)]
+#[path = "../../resident/src/status.rs"]
+mod status;
+
+#[cfg(windows)]
+use std::convert::TryFrom;
+#[cfg(windows)]
+use std::sync::{mpsc, OnceLock};
+#[cfg(windows)]
+use std::time::Instant;
+
+// The window procedure hands events to the status thread through this
+#[cfg(windows)]
+static EVENTS: OnceLock<mpsc::Sender<status::StubEvent>> = OnceLock::new();
+
 #[cfg(windows)]
 fn main() -> windows::core::Result<()> {
-    // TODO: some argument key
-    if std::env::args().count() == 1 {
-        execute!(MessageBoxW(
-            HWND(0),
-            w!("Don't run this application manually."),
-            w!("Error"),
-            MB_OK | MB_ICONERROR
-        ))?;
-        return Ok(());
-    }
+    // Without a valid request from the resident on stdin, somebody started us by hand
+    let request = match handshake::Request::read(&mut std::io::stdin().lock()) {
+        Ok(request) => request,
+        Err(_) => {
+            execute!(MessageBoxW(
+                HWND(0),
+                w!("Don't run this application manually."),
+                w!("Error"),
+                MB_OK | MB_ICONERROR
+            ))?;
+            return Ok(());
+        }
+    };
+    // Only a resident listening on the channel it named can welcome us. Anything else that feeds
+    // the stub a request gets no window.
+    let Some(channel) = &request.status else { return Ok(()) };
+    let Ok(pipe) = join_resident(channel, &request.token) else { return Ok(()) };
 
     let module_handle: HINSTANCE = unsafe {GetModuleHandleW(None) }?;
     let cursor: HCURSOR = unsafe {LoadCursorW(None, IDC_ARROW) }?;
-    let class_name = w!("stub_class");
+    let persona = &request.persona;
+    let class_name: Vec<u16> = to_wide(persona.window_class.as_deref().unwrap_or("stub_class"));
+    let title: Vec<u16> = to_wide(persona.window_title.as_deref().unwrap_or("The window"));
 
     let win_class = WNDCLASSEXW {
         cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
@@ -36,7 +68,7 @@ fn main() -> windows::core::Result<()> {
         cbWndExtra: 0,
         hInstance: module_handle,
         hCursor: cursor,
-        lpszClassName: class_name,
+        lpszClassName: PCWSTR(class_name.as_ptr()),
 
         ..Default::default()
     };
@@ -46,8 +78,8 @@ fn main() -> windows::core::Result<()> {
 
     let _win_handle: HWND = execute!(CreateWindowExW(
         Default::default(),
-        class_name,
-        w!("The window"),
+        PCWSTR(class_name.as_ptr()),
+        PCWSTR(title.as_ptr()),
         WS_DISABLED,
         CW_USEDEFAULT,
         CW_USEDEFAULT,
@@ -59,6 +91,20 @@ fn main() -> windows::core::Result<()> {
         None,
     ))?;
 
+    // The window exists, so FindWindow checks already see us. A resident that doesn't listen
+    // anymore has given up on this stub.
+    if handshake::write_started(&mut std::io::stdout().lock(), &request.token).is_err() {
+        return Ok(());
+    }
+    // Without the channel the stub still does its job, the resident only knows less about it
+    let _ = report_status(pipe);
+    // Ends the message loop once the resident asks us to exit or goes away
+    let main_thread: u32 = unsafe { GetCurrentThreadId() };
+    std::thread::spawn(move || {
+        handshake::wait_shutdown(&mut std::io::stdin().lock());
+        unsafe { PostThreadMessageW(main_thread, WM_QUIT, WPARAM(0), LPARAM(0)) };
+    });
+
     let mut message = MSG::default();
 
     unsafe {
@@ -70,11 +116,103 @@ fn main() -> windows::core::Result<()> {
     Ok(())
 }
 
+// Introduces the stub on the status channel and waits until the resident welcomes it
+#[cfg(windows)]
+fn join_resident(channel: &handshake::StatusChannel, token: &str) -> std::io::Result<std::fs::File> {
+    use std::io::{BufReader, Error, ErrorKind};
+
+    let deadline = Instant::now() + status::WELCOME_TIMEOUT;
+    let mut pipe = open_channel(&channel.name, deadline)?;
+    status::Message::Hello { token: token.to_string(), stub_id: channel.stub_id }.write(&mut pipe)?;
+    // Reading has no timeout of its own
+    let mut answers = BufReader::new(pipe.try_clone()?);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(status::read_welcome(&mut answers));
+    });
+    receiver
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, "resident didn't welcome the stub")))?;
+    Ok(pipe)
+}
+
+// Opens the status channel. All its instances are busy while the resident is busy accepting
+// another stub, so this waits for a free one until `deadline`.
+#[cfg(windows)]
+fn open_channel(name: &str, deadline: Instant) -> std::io::Result<std::fs::File> {
+    loop {
+        match std::fs::OpenOptions::new().read(true).write(true).open(name) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY.0 as i32) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                if timeout.is_zero() {
+                    return Err(e);
+                }
+                // Another stub may take the free instance first, then the open fails again
+                let wide_name = to_wide(name);
+                let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX - 1);
+                if !unsafe { WaitNamedPipeW(PCWSTR(wide_name.as_ptr()), timeout_ms) }.as_bool() {
+                    return Err(e);
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+// Tells the resident the window is up, then sends events and heartbeats from a separate thread
+#[cfg(windows)]
+fn report_status(mut pipe: std::fs::File) -> std::io::Result<()> {
+    use status::Message;
+
+    Message::Ready.write(&mut pipe)?;
+    let (sender, receiver) = mpsc::channel();
+    let _ = EVENTS.set(sender);
+    std::thread::spawn(move || loop {
+        let message = match receiver.recv_timeout(status::HEARTBEAT_INTERVAL) {
+            Ok(event) => Message::Event(event),
+            Err(mpsc::RecvTimeoutError::Timeout) => Message::Heartbeat,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        // The resident is gone
+        if message.write(&mut pipe).is_err() {
+            break;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn report_event(event: status::StubEvent) {
+    if let Some(sender) = EVENTS.get() {
+        let _ = sender.send(event);
+    }
+}
+
+// Null terminated UTF-16 string for Win32 calls
+#[cfg(windows)]
+fn to_wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(windows)]
 unsafe extern "system" fn wndproc(
     window: HWND,
     message: u32,
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
+    match message {
+        WM_CLOSE => report_event(status::StubEvent::CloseRequested),
+        WM_QUERYENDSESSION => report_event(status::StubEvent::SessionEnding),
+        // Nothing in the stub sends these, somebody is probing the window.
+        // Registered messages above 0xBFFF are shell broadcasts everybody gets.
+        WM_COPYDATA | WM_USER..=0xBFFF => report_event(status::StubEvent::ForeignMessage(message)),
+        _ => {}
+    }
     DefWindowProcW(window, message, wparam, lparam)
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("des-stub runs on Windows only.");
+}